@@ -0,0 +1,116 @@
+use core::mem::size_of;
+
+/// Root System Description Pointer, version 1 part.
+///
+/// [`https://wiki.osdev.org/RSDP`]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+}
+
+/// Header shared by every system description table.
+///
+/// [`https://wiki.osdev.org/RSDT`]
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// The whole table, including the header.
+    pub fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, self.length as usize)
+        }
+    }
+
+    /// The table's contents following the header.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes()[size_of::<Self>()..]
+    }
+
+    fn is_valid(&self) -> bool {
+        self.length as usize >= size_of::<Self>() && checksum(self.bytes()) == 0
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Searches the first KiB of the EBDA and the BIOS area below 1 MiB for the RSDP.
+pub fn find_rsdp() -> Option<&'static Rsdp> {
+    // Real mode segment of the EBDA
+    let ebda = unsafe { core::ptr::read_volatile(0x40E as *const u16) } as usize * 16;
+
+    let ranges = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+    ranges
+        .into_iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .map(|addr| unsafe { &*(addr as *const Rsdp) })
+        .find(|rsdp| {
+            let bytes = unsafe {
+                core::slice::from_raw_parts(*rsdp as *const Rsdp as *const u8, size_of::<Rsdp>())
+            };
+            rsdp.signature == *b"RSD PTR " && checksum(bytes) == 0
+        })
+}
+
+/// Looks up the table with `signature`, e.g. `b"FACP"`, in the RSDT.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let rsdp = find_rsdp()?;
+    let rsdt = unsafe { &*(rsdp.rsdt_address as *const SdtHeader) };
+    if rsdt.signature != *b"RSDT" || !rsdt.is_valid() {
+        return None;
+    }
+
+    rsdt.data()
+        .chunks_exact(4)
+        .map(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]))
+        .map(|addr| unsafe { &*(addr as *const SdtHeader) })
+        .find(|table| table.signature == *signature && table.is_valid())
+}
+
+/// The CMOS register holding the century, if the FADT names one.
+///
+/// [`https://wiki.osdev.org/FADT`]
+pub fn century_register() -> Option<u8> {
+    const CENTURY_OFFSET: usize = 108;
+
+    let fadt = find_table(b"FACP")?;
+    match fadt.bytes().get(CENTURY_OFFSET) {
+        Some(0) | None => None,
+        Some(register) => Some(*register),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(acpi_tables, {
+        test_assert_eq!(20, size_of::<Rsdp>());
+        test_assert_eq!(36, size_of::<SdtHeader>());
+
+        // QEMU's BIOS provides ACPI tables
+        test_assert!(find_rsdp().is_some());
+        test_assert!(find_table(b"FACP").is_some());
+        test_assert!(find_table(b"NONE").is_none());
+    });
+}
@@ -0,0 +1,329 @@
+use crate::{hpet, interrupt, pit, time, tsc};
+use core::{
+    ops::{Add, AddAssign, Sub, SubAssign},
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+pub use core::time::Duration;
+
+static SOURCE: AtomicU8 = AtomicU8::new(ClockSource::None as u8);
+/// Added to the raw source time, so that time continues where it left off when the source changes.
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+/// The latest time handed out, which no later reading may go below.
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+/// Hardware backing the monotonic clock.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// Time does not advance.
+    None,
+    /// [`pit::ticks`], with the resolution of one tick.
+    Pit,
+    /// The calibrated time stamp counter, see [`tsc::calibrate_with_pit`].
+    Tsc,
+    /// [`time::rtc_ticks`], for when the PIT is unavailable.
+    Rtc,
+    /// The HPET main counter, see [`hpet::Hpet`].
+    Hpet,
+}
+
+impl ClockSource {
+    const fn from_value(value: u8) -> Self {
+        match value {
+            1 => Self::Pit,
+            2 => Self::Tsc,
+            3 => Self::Rtc,
+            4 => Self::Hpet,
+            _ => Self::None,
+        }
+    }
+
+    fn uptime_ns(&self) -> Option<u64> {
+        match self {
+            Self::None => Some(0),
+            Self::Pit => pit::uptime_ns(),
+            Self::Tsc => tsc::uptime_ns(),
+            Self::Rtc => time::rtc_uptime_ns(),
+            Self::Hpet => hpet::uptime_ns(),
+        }
+    }
+}
+
+pub fn source() -> ClockSource {
+    ClockSource::from_value(SOURCE.load(Ordering::Acquire))
+}
+
+/// Switches the clock to `source`. Returns `false`, leaving the clock untouched, if the source is
+/// not running.
+pub fn set_source(source: ClockSource) -> bool {
+    interrupt::without_interrupts(|| {
+        let Some(raw) = source.uptime_ns() else {
+            return false;
+        };
+
+        let now = uptime_ns();
+        OFFSET.store(now.wrapping_sub(raw), Ordering::Release);
+        SOURCE.store(source as u8, Ordering::Release);
+        true
+    })
+}
+
+/// Switches to the most precise running source.
+///
+/// A TSC that is not invariant may change rate with the CPU's power state, so it is only used
+/// when nothing else is running.
+pub fn select_best_source() -> ClockSource {
+    let sources = if tsc::is_invariant() {
+        [
+            ClockSource::Tsc,
+            ClockSource::Hpet,
+            ClockSource::Pit,
+            ClockSource::Rtc,
+        ]
+    } else {
+        [
+            ClockSource::Hpet,
+            ClockSource::Pit,
+            ClockSource::Rtc,
+            ClockSource::Tsc,
+        ]
+    };
+
+    for source in sources {
+        if set_source(source) {
+            return source;
+        }
+    }
+    source()
+}
+
+/// Monotonic time since the clock was started in nanoseconds.
+pub fn uptime_ns() -> u64 {
+    let raw = source().uptime_ns().unwrap_or(0);
+    let ns = raw.wrapping_add(OFFSET.load(Ordering::Acquire));
+    LAST.fetch_max(ns, Ordering::AcqRel).max(ns)
+}
+
+pub fn uptime_us() -> u64 {
+    uptime_ns() / 1_000
+}
+
+/// Also serves `DG_GetTicksMs`.
+pub fn uptime_ms() -> u64 {
+    uptime_ns() / 1_000_000
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_nanos(uptime_ns())
+}
+
+/// Blocks for at least `duration`.
+///
+/// While interrupts are enabled and the PIT is running, the CPU halts until the tick that is
+/// still due before the deadline, and only spins for the remaining fraction of a tick. Otherwise,
+/// e.g. inside [`interrupt::without_interrupts`], this busy-waits. There is no scheduler to
+/// yield to yet. Returns immediately if no clock source is selected, as time would not advance.
+pub fn sleep(duration: Duration) {
+    if source() == ClockSource::None {
+        return;
+    }
+
+    let Some(deadline) = Instant::now().checked_add(duration) else {
+        return;
+    };
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        let remaining = deadline - now;
+        match pit::tick_period_ns() {
+            Some(period)
+                if interrupt::interrupts_enabled() && remaining >= Duration::from_nanos(period) =>
+            {
+                interrupt::halt()
+            }
+            _ => core::hint::spin_loop(),
+        }
+    }
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep(Duration::from_millis(ms));
+}
+
+pub fn sleep_us(us: u64) {
+    sleep(Duration::from_micros(us));
+}
+
+/// A point in monotonic time, see [`uptime_ns`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(uptime_ns())
+    }
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// Saturates to zero if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Self)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_sub(nanos).map(Self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{idt, test, test_case};
+
+    test_case!(instant_arithmetic, {
+        let start = Instant::from_nanos(1_000);
+        let later = start + Duration::from_micros(5);
+        test_assert_eq!(6_000, later.as_nanos());
+        test_assert_eq!(Duration::from_micros(5), later - start);
+        test_assert_eq!(Duration::ZERO, start - later);
+        test_assert_eq!(None, start.checked_duration_since(later));
+        test_assert_eq!(Some(start), later.checked_sub(Duration::from_micros(5)));
+        test_assert_eq!(None, start.checked_sub(Duration::from_micros(5)));
+        test_assert_eq!(None, start.checked_add(Duration::MAX));
+
+        let mut instant = start;
+        instant += Duration::from_nanos(10);
+        instant -= Duration::from_nanos(5);
+        test_assert_eq!(1_005, instant.as_nanos());
+    });
+
+    test_case!(clock_monotonic, {
+        let a = Instant::now();
+        let b = Instant::now();
+        test_assert!(b >= a);
+        test_assert!(uptime_ms() <= uptime_us());
+    });
+
+    test_case!(clock_sleep, {
+        // Tests run before the kernel selects a source, so fall back to a freshly calibrated TSC
+        let previous = source();
+        if tsc::frequency().is_none() {
+            tsc::calibrate_with_pit(test::pit(), 20);
+        }
+        test_assert!(set_source(ClockSource::Tsc));
+
+        for (duration, tolerance) in [
+            (Duration::from_millis(10), Duration::from_millis(1)),
+            (Duration::from_micros(500), Duration::from_micros(100)),
+        ] {
+            let start = Instant::now();
+            sleep(duration);
+            let elapsed = start.elapsed();
+            test_assert!(elapsed >= duration);
+            test_assert!(elapsed < duration + tolerance);
+        }
+
+        let start = Instant::now();
+        sleep_us(0);
+        test_assert!(start.elapsed() < Duration::from_micros(100));
+
+        set_source(previous);
+    });
+
+    test_case!(clock_sleep_halting, {
+        crate::gdt::init();
+        idt::init();
+        let pit = test::pit();
+        let previous = source();
+        if tsc::frequency().is_none() {
+            tsc::calibrate_with_pit(pit, 20);
+        }
+        test_assert!(set_source(ClockSource::Tsc));
+
+        let ticks = pit::ticks();
+        let mut elapsed = [Duration::ZERO; 2];
+        unsafe { core::arch::asm!("sti") };
+        let start = Instant::now();
+        sleep_ms(10);
+        elapsed[0] = start.elapsed();
+        let start = Instant::now();
+        sleep_us(5_500);
+        elapsed[1] = start.elapsed();
+        unsafe { core::arch::asm!("cli") };
+
+        // At 1 kHz, the PIT interrupt woke the halted CPU on every tick of both sleeps
+        test_assert!(pit::ticks() - ticks >= 14);
+        for (elapsed, duration) in elapsed
+            .into_iter()
+            .zip([Duration::from_millis(10), Duration::from_micros(5_500)])
+        {
+            test_assert!(elapsed >= duration);
+            test_assert!(elapsed < duration + Duration::from_millis(2));
+        }
+
+        set_source(previous);
+    });
+}
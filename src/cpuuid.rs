@@ -59,6 +59,61 @@ pub fn get_cpu_features() -> Vec<CpuidFeatureEdx> {
     features
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Executes `cpuid` for `leaf`, with sub-leaf 0.
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+    unsafe {
+        core::arch::asm!(
+            "cpuid",
+            inout("eax") leaf => eax,
+            out("ebx") ebx,
+            inout("ecx") 0 => ecx,
+            out("edx") edx,
+        );
+    }
+
+    CpuidResult { eax, ebx, ecx, edx }
+}
+
+pub fn has_feature(feature: CpuidFeatureEdx) -> bool {
+    cpuid(0x1).edx & feature as u32 != 0
+}
+
+/// [`https://wiki.osdev.org/Model_Specific_Registers`]
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    core::arch::asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags)
+    );
+    ((high as u64) << 32) | low as u64
+}
+
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    core::arch::asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
+
 #[repr(u32)]
 #[allow(non_camel_case_types, clippy::upper_case_acronyms, unused)]
 /// Only found within newer chips?
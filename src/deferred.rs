@@ -0,0 +1,128 @@
+use crate::lock::spinlock::SpinLock;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    pub static ref DEFERRED_WORK: DeferredWork = DeferredWork::default();
+}
+
+/// Bottom half processing for interrupt handlers.
+///
+/// Work is registered up front as a [`Tasklet`], outside of interrupt context. An interrupt
+/// handler then only flags the tasklet with [`TaskletHandle::schedule`], which neither allocates
+/// nor locks, and the tasklet body runs on the next call to [`DeferredWork::run_pending`] with
+/// interrupts enabled.
+///
+/// [`https://www.kernel.org/doc/html/latest/core-api/workqueue.html`]
+pub struct DeferredWork {
+    pending: AtomicBool,
+    tasklets: SpinLock<Vec<Arc<Tasklet>>>,
+}
+
+unsafe impl Send for DeferredWork {}
+
+impl Default for DeferredWork {
+    fn default() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            tasklets: SpinLock::new(Vec::new()),
+        }
+    }
+}
+
+impl DeferredWork {
+    /// Registers `func` as a tasklet. Must not be called from an interrupt handler.
+    pub fn register(&'static self, func: impl FnMut() + 'static) -> TaskletHandle {
+        let tasklet = Arc::new(Tasklet {
+            scheduled: AtomicBool::new(false),
+            func: SpinLock::new(Box::new(func)),
+        });
+        self.tasklets.lock().push(tasklet.clone());
+
+        TaskletHandle {
+            owner: self,
+            tasklet,
+        }
+    }
+
+    /// Runs every scheduled tasklet until none are left pending.
+    ///
+    /// Called from the kernel's main loop. Tasklets may schedule themselves or register new
+    /// tasklets, but must not call `run_pending` recursively.
+    pub fn run_pending(&self) {
+        while self.pending.swap(false, Ordering::AcqRel) {
+            let mut index = 0;
+            loop {
+                // The lock is released before running the tasklet so that it may register more
+                // work.
+                let tasklet = self.tasklets.lock().get(index).cloned();
+                let Some(tasklet) = tasklet else {
+                    break;
+                };
+
+                if tasklet.scheduled.swap(false, Ordering::AcqRel) {
+                    (tasklet.func.lock())();
+                }
+                index += 1;
+            }
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+pub struct Tasklet {
+    scheduled: AtomicBool,
+    func: SpinLock<Box<dyn FnMut()>>,
+}
+
+// The kernel runs on a single core, and `func` is only called from `run_pending`, never from an
+// interrupt handler.
+unsafe impl Send for Tasklet {}
+unsafe impl Sync for Tasklet {}
+
+#[derive(Clone)]
+pub struct TaskletHandle {
+    owner: &'static DeferredWork,
+    tasklet: Arc<Tasklet>,
+}
+
+unsafe impl Send for TaskletHandle {}
+
+impl TaskletHandle {
+    /// Marks the tasklet to be run on the next [`DeferredWork::run_pending`]. Safe to call from
+    /// interrupt handlers.
+    ///
+    /// Scheduling an already scheduled tasklet does nothing; it runs once.
+    pub fn schedule(&self) {
+        self.tasklet.scheduled.store(true, Ordering::Release);
+        self.owner.pending.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+    use core::sync::atomic::AtomicUsize;
+
+    test_case!(deferred_work, {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let handle = DEFERRED_WORK.register(|| {
+            RUNS.fetch_add(1, Ordering::Relaxed);
+        });
+        DEFERRED_WORK.run_pending();
+        test_assert_eq!(0, RUNS.load(Ordering::Relaxed));
+
+        handle.schedule();
+        handle.schedule();
+        test_assert!(DEFERRED_WORK.has_pending());
+        DEFERRED_WORK.run_pending();
+        test_assert_eq!(1, RUNS.load(Ordering::Relaxed));
+        test_assert!(!DEFERRED_WORK.has_pending());
+    });
+}
@@ -1,10 +1,82 @@
-use core::arch::asm;
+use crate::tss::{self, TaskStateSegment, DOUBLE_FAULT_TSS, TSS};
+use core::{arch::asm, cell::RefCell};
 
-static GDT: [GdtDescriptor; 3] = [
-    GdtDescriptor::null(),
-    GdtDescriptor::new(0, 0xFFFFF, Granularity::KiloBytes, true),
-    GdtDescriptor::new(0, 0xFFFFF, Granularity::KiloBytes, false),
-];
+const NUM_GDT_DESC: usize = 5;
+
+/// https://wiki.osdev.org/Segment_Selector
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentSelector(u16);
+
+impl SegmentSelector {
+    pub const GDT_CODE: Self = Self::new(GdtIndex::Code);
+    pub const GDT_DATA: Self = Self::new(GdtIndex::Data);
+    pub const TSS: Self = Self::new(GdtIndex::Tss);
+    pub const DOUBLE_FAULT_TSS: Self = Self::new(GdtIndex::DoubleFaultTss);
+
+    pub const fn new(index: GdtIndex) -> Self {
+        Self(index.value() << 3)
+    }
+
+    pub const fn from_value(value: u16) -> Self {
+        Self(value)
+    }
+
+    pub const fn encoded_value(&self) -> u16 {
+        self.0
+    }
+
+    const fn index(&self) -> usize {
+        (self.0 >> 3) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GdtIndex {
+    Code,
+    Data,
+    Tss,
+    DoubleFaultTss,
+}
+
+impl GdtIndex {
+    pub const fn value(&self) -> u16 {
+        match self {
+            Self::Code => 1,
+            Self::Data => 2,
+            Self::Tss => 3,
+            Self::DoubleFaultTss => 4,
+        }
+    }
+}
+
+static GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+
+struct GlobalDescriptorTable {
+    entries: RefCell<[GdtDescriptor; NUM_GDT_DESC]>,
+}
+
+unsafe impl Sync for GlobalDescriptorTable {}
+
+impl GlobalDescriptorTable {
+    const fn new() -> Self {
+        Self {
+            entries: RefCell::new([
+                GdtDescriptor::null(),
+                GdtDescriptor::new(0, 0xFFFFF, Granularity::KiloBytes, true),
+                GdtDescriptor::new(0, 0xFFFFF, Granularity::KiloBytes, false),
+                // Task state segments, filled in by `init`
+                GdtDescriptor::null(),
+                GdtDescriptor::null(),
+            ]),
+        }
+    }
+
+    fn set_entry(&self, descriptor: GdtDescriptor, selector: SegmentSelector) {
+        let entry = selector.index();
+        debug_assert!(entry < NUM_GDT_DESC);
+        self.entries.borrow_mut()[entry] = descriptor;
+    }
+}
 
 #[repr(C, packed)]
 struct GdtPointer {
@@ -13,8 +85,22 @@ struct GdtPointer {
 }
 
 pub fn init() {
-    let size_of_gdt = 8 * 3;
-    let gdt_addr = &GDT as *const GdtDescriptor as u32;
+    tss::init(
+        SegmentSelector::GDT_CODE.encoded_value(),
+        SegmentSelector::GDT_DATA.encoded_value(),
+    );
+    let tss_limit = TaskStateSegment::SIZE as u32 - 1;
+    GDT.set_entry(
+        GdtDescriptor::tss(TSS.addr(), tss_limit),
+        SegmentSelector::TSS,
+    );
+    GDT.set_entry(
+        GdtDescriptor::tss(DOUBLE_FAULT_TSS.addr(), tss_limit),
+        SegmentSelector::DOUBLE_FAULT_TSS,
+    );
+
+    let size_of_gdt = 8 * NUM_GDT_DESC;
+    let gdt_addr = GDT.entries.as_ptr() as u32;
 
     let gdt_ptr = GdtPointer {
         limit: (size_of_gdt - 1) as u16,
@@ -24,17 +110,22 @@ pub fn init() {
     unsafe {
         asm!(
             "lgdt ({0})",
-            "jmp $0x08, $1f",
+            "jmp ${code}, $1f",
             "1:",
-            "mov $0x10, %ax",
+            "mov ${data}, %ax",
             "mov %ax, %ds",
             "mov %ax, %es",
             "mov %ax, %fs",
             "mov %ax, %gs",
             "mov %ax, %ss",
             in(reg) &gdt_ptr,
+            code = const SegmentSelector::GDT_CODE.encoded_value(),
+            data = const SegmentSelector::GDT_DATA.encoded_value(),
             options(att_syntax)
         );
+
+        // The task register must hold a valid TSS for the CPU to save state into on a task switch
+        asm!("ltr {0:x}", in(reg) SegmentSelector::TSS.encoded_value(), options(nostack, preserves_flags));
     }
 }
 
@@ -47,6 +138,7 @@ pub fn read_gdtr() -> u64 {
 }
 
 #[allow(unused)]
+#[derive(Clone, Copy)]
 struct GdtDescriptor(u64);
 
 #[allow(unused)]
@@ -97,6 +189,25 @@ impl GdtDescriptor {
         slf
     }
 
+    /// A 32 bit available TSS system segment.
+    ///
+    /// https://wiki.osdev.org/Global_Descriptor_Table#System_Segment_Descriptor
+    pub const fn tss(base: u32, limit: u32) -> Self {
+        let tss_available = 0x9;
+        let access_byte = Self::PRESENT_BIT | Self::PRIVILEGE | tss_available;
+
+        let mut slf = Self::null();
+        Self::write_entry(
+            base,
+            limit,
+            access_byte,
+            0,
+            &mut slf as *mut Self as *mut u8,
+        );
+
+        slf
+    }
+
     fn base(&self) -> u32 {
         let mut base = self.bits(16, 24);
         let upper = self.bits(56, 8);
@@ -169,5 +280,10 @@ mod tests {
         test_assert_eq!(0xff33, gdt.base());
         test_assert_eq!(0xdeaf, gdt.limit());
         test_assert_eq!(0b0100, gdt.flags());
+        let gdt = GdtDescriptor::tss(0xdeadbeef, 103);
+        test_assert_eq!(0xdeadbeef, gdt.base());
+        test_assert_eq!(103, gdt.limit());
+        test_assert_eq!(0x89, gdt.access());
+        test_assert_eq!(0, gdt.flags());
     });
 }
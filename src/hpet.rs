@@ -0,0 +1,355 @@
+use crate::{acpi, clock::Duration};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// MMIO base of the HPET backing [`uptime_ns`], zero if there is none.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+
+/// Time since the HPET main counter was enabled in nanoseconds, or `None` if no HPET with a 64
+/// bit counter was found.
+pub fn uptime_ns() -> Option<u64> {
+    match BASE.load(Ordering::Acquire) {
+        0 => None,
+        base => {
+            let counter = read_counter(base);
+            let period = PERIOD_FS.load(Ordering::Acquire);
+            Some((counter as u128 * period as u128 / 1_000_000) as u64)
+        }
+    }
+}
+
+fn read_register(base: usize, offset: usize) -> u64 {
+    // The registers are 64 bits wide, but only 32 bit accesses are available
+    unsafe {
+        let low = core::ptr::read_volatile((base + offset) as *const u32);
+        let high = core::ptr::read_volatile((base + offset + 4) as *const u32);
+        ((high as u64) << 32) | low as u64
+    }
+}
+
+fn write_register(base: usize, offset: usize, value: u64) {
+    unsafe {
+        core::ptr::write_volatile((base + offset) as *mut u32, value as u32);
+        core::ptr::write_volatile((base + offset + 4) as *mut u32, (value >> 32) as u32);
+    }
+}
+
+fn read_counter(base: usize) -> u64 {
+    // The low half may wrap between the two reads
+    loop {
+        let high =
+            unsafe { core::ptr::read_volatile((base + Hpet::MAIN_COUNTER + 4) as *const u32) };
+        let low = unsafe { core::ptr::read_volatile((base + Hpet::MAIN_COUNTER) as *const u32) };
+        let high_again =
+            unsafe { core::ptr::read_volatile((base + Hpet::MAIN_COUNTER + 4) as *const u32) };
+        if high == high_again {
+            return ((high as u64) << 32) | low as u64;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HpetError {
+    /// ACPI has no `HPET` table.
+    NotFound,
+    /// The registers are not memory mapped.
+    UnsupportedAddressSpace,
+    /// The registers lie above 4 GiB, out of reach without PAE.
+    AddressOutOfRange(u64),
+    /// The counter period is zero or above the 100 ns the specification allows.
+    InvalidPeriod,
+    NoSuchComparator,
+    PeriodicUnsupported,
+    /// The requested period or delay does not fit the comparator.
+    OutOfRange,
+}
+
+/// High Precision Event Timer.
+///
+/// The main counter runs at a fixed rate of at least 10 MHz and backs
+/// [`crate::clock::ClockSource::Hpet`]. Comparators raise their interrupt when the counter
+/// reaches them. Without an IO APIC driver their interrupts can only be delivered through legacy
+/// replacement routing, see [`Hpet::set_legacy_replacement`], otherwise expiry is observed with
+/// [`Comparator::poll`].
+///
+/// [`https://wiki.osdev.org/HPET`]
+pub struct Hpet {
+    base: usize,
+    period_fs: u64,
+    comparators: u8,
+    counter_64: bool,
+}
+
+impl Hpet {
+    const CAPABILITIES: usize = 0x000;
+    const CONFIGURATION: usize = 0x010;
+    const INTERRUPT_STATUS: usize = 0x020;
+    const MAIN_COUNTER: usize = 0x0F0;
+
+    const ENABLE: u64 = 1 << 0;
+    const LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+    /// Offset of the base address structure in the ACPI table.
+    const TABLE_BASE_ADDRESS: usize = 40;
+    /// Femtoseconds.
+    const MAX_PERIOD: u64 = 100_000_000;
+
+    /// Finds the HPET through ACPI and starts its main counter.
+    pub fn new() -> Result<Self, HpetError> {
+        let table = acpi::find_table(b"HPET").ok_or(HpetError::NotFound)?;
+        let address = table
+            .bytes()
+            .get(Self::TABLE_BASE_ADDRESS..Self::TABLE_BASE_ADDRESS + 12)
+            .ok_or(HpetError::NotFound)?;
+        // Generic address structure, address space 0 is system memory
+        if address[0] != 0 {
+            return Err(HpetError::UnsupportedAddressSpace);
+        }
+        let mut base = [0; 8];
+        base.copy_from_slice(&address[4..12]);
+        let base = u64::from_le_bytes(base);
+        let base = u32::try_from(base).map_err(|_| HpetError::AddressOutOfRange(base))? as usize;
+
+        let capabilities = read_register(base, Self::CAPABILITIES);
+        let period_fs = capabilities >> 32;
+        if period_fs == 0 || period_fs > Self::MAX_PERIOD {
+            return Err(HpetError::InvalidPeriod);
+        }
+
+        let slf = Self {
+            base,
+            period_fs,
+            comparators: ((capabilities >> 8) & 0x1F) as u8 + 1,
+            counter_64: capabilities & (1 << 13) != 0,
+        };
+
+        let config = read_register(base, Self::CONFIGURATION);
+        write_register(base, Self::CONFIGURATION, config | Self::ENABLE);
+
+        // A 32 bit counter wraps within minutes, too soon for a clock source
+        if slf.counter_64 {
+            PERIOD_FS.store(period_fs, Ordering::Release);
+            BASE.store(base, Ordering::Release);
+        }
+
+        Ok(slf)
+    }
+
+    /// Counter frequency in Hz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    /// Counter period in femtoseconds.
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        self.comparators
+    }
+
+    pub fn counter(&self) -> u64 {
+        if self.counter_64 {
+            read_counter(self.base)
+        } else {
+            read_register(self.base, Self::MAIN_COUNTER) & 0xFFFF_FFFF
+        }
+    }
+
+    pub fn comparator(&self, index: u8) -> Result<Comparator<'_>, HpetError> {
+        if index >= self.comparators {
+            return Err(HpetError::NoSuchComparator);
+        }
+        Ok(Comparator { hpet: self, index })
+    }
+
+    /// Routes comparator 0 to IRQ0 and comparator 1 to IRQ8, disconnecting the PIT and the RTC
+    /// from the PIC. Handlers registered for those lines then fire on comparator expiry.
+    pub fn set_legacy_replacement(&self, enabled: bool) {
+        let config = read_register(self.base, Self::CONFIGURATION);
+        let config = if enabled {
+            config | Self::LEGACY_REPLACEMENT
+        } else {
+            config & !Self::LEGACY_REPLACEMENT
+        };
+        write_register(self.base, Self::CONFIGURATION, config);
+    }
+
+    /// Number of counter ticks in `duration`, rounded up.
+    fn ticks(&self, duration: Duration) -> Option<u64> {
+        let fs = duration.as_nanos().checked_mul(1_000_000)?;
+        u64::try_from(fs.div_ceil(self.period_fs as u128)).ok()
+    }
+}
+
+/// One of the HPET's timers.
+pub struct Comparator<'a> {
+    hpet: &'a Hpet,
+    index: u8,
+}
+
+impl Comparator<'_> {
+    /// Level triggered, so expiry is latched in the interrupt status register.
+    const LEVEL_TRIGGERED: u64 = 1 << 1;
+    const INTERRUPT_ENABLE: u64 = 1 << 2;
+    const PERIODIC: u64 = 1 << 3;
+    const PERIODIC_CAPABLE: u64 = 1 << 4;
+    const SIZE_64: u64 = 1 << 5;
+    const SET_ACCUMULATOR: u64 = 1 << 6;
+    const MODE_32: u64 = 1 << 8;
+    const ROUTE_SHIFT: u64 = 9;
+    const ROUTE_MASK: u64 = 0x1F << Self::ROUTE_SHIFT;
+
+    fn config_offset(&self) -> usize {
+        0x100 + 0x20 * self.index as usize
+    }
+
+    fn comparator_offset(&self) -> usize {
+        0x108 + 0x20 * self.index as usize
+    }
+
+    fn config(&self) -> u64 {
+        read_register(self.hpet.base, self.config_offset())
+    }
+
+    fn set_config(&self, config: u64) {
+        write_register(self.hpet.base, self.config_offset(), config);
+    }
+
+    /// The configuration with the interrupt routed to the highest IO APIC input available, which
+    /// is least likely to be shared with an ISA line on the PIC.
+    fn routed_config(&self) -> u64 {
+        let config = self.config();
+        let capable = (config >> 32) as u32;
+        let route = 31u32.saturating_sub(capable.leading_zeros()) as u64;
+        (config & !Self::ROUTE_MASK) | (route << Self::ROUTE_SHIFT)
+    }
+
+    pub fn supports_periodic(&self) -> bool {
+        self.config() & Self::PERIODIC_CAPABLE != 0
+    }
+
+    /// Fires once, `delay` from now.
+    pub fn start_one_shot(&self, delay: Duration) -> Result<(), HpetError> {
+        let wide = self.config() & Self::SIZE_64 != 0 && self.hpet.counter_64;
+        let ticks = self.hpet.ticks(delay).ok_or(HpetError::OutOfRange)?;
+        if !wide && ticks > u32::MAX as u64 {
+            return Err(HpetError::OutOfRange);
+        }
+
+        self.stop();
+        let mode = if wide { 0 } else { Self::MODE_32 };
+        let config = self.routed_config() & !(Self::PERIODIC | Self::MODE_32);
+        self.set_config(config | Self::LEVEL_TRIGGERED | mode);
+
+        let target = self.hpet.counter().wrapping_add(ticks);
+        write_register(self.hpet.base, self.comparator_offset(), target);
+        self.set_config(config | Self::LEVEL_TRIGGERED | mode | Self::INTERRUPT_ENABLE);
+        Ok(())
+    }
+
+    /// Fires every `period`, starting one period from now.
+    ///
+    /// Runs the comparator in 32 bit mode, which limits the period to 2^32 counter ticks.
+    pub fn start_periodic(&self, period: Duration) -> Result<(), HpetError> {
+        if !self.supports_periodic() {
+            return Err(HpetError::PeriodicUnsupported);
+        }
+        let ticks = self.hpet.ticks(period).ok_or(HpetError::OutOfRange)?;
+        if ticks == 0 || ticks > u32::MAX as u64 {
+            return Err(HpetError::OutOfRange);
+        }
+
+        self.stop();
+        let config = self.routed_config() | Self::LEVEL_TRIGGERED | Self::PERIODIC | Self::MODE_32;
+        self.set_config(config | Self::SET_ACCUMULATOR);
+
+        // With the accumulator flag set, the first write sets the comparator and the second one
+        // the period.
+        let comparator = self.comparator_offset();
+        let target = (self.hpet.counter() as u32).wrapping_add(ticks as u32);
+        unsafe {
+            core::ptr::write_volatile((self.hpet.base + comparator) as *mut u32, target);
+            core::ptr::write_volatile((self.hpet.base + comparator) as *mut u32, ticks as u32);
+        }
+        self.set_config(config | Self::INTERRUPT_ENABLE);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.set_config(self.config() & !(Self::INTERRUPT_ENABLE | Self::PERIODIC));
+        self.acknowledge();
+    }
+
+    /// Returns whether the comparator fired since the last poll, and acknowledges it.
+    pub fn poll(&self) -> bool {
+        let status = read_register(self.hpet.base, Hpet::INTERRUPT_STATUS);
+        let fired = status & (1 << self.index) != 0;
+        if fired {
+            self.acknowledge();
+        }
+        fired
+    }
+
+    fn acknowledge(&self) {
+        // Writing a one clears the status bit
+        write_register(self.hpet.base, Hpet::INTERRUPT_STATUS, 1 << self.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(hpet, {
+        // QEMU provides an HPET
+        let hpet = Hpet::new();
+        test_assert!(hpet.is_ok());
+        let hpet = hpet.unwrap();
+        test_assert!(hpet.frequency() >= 10_000_000);
+        test_assert!(hpet.comparator_count() >= 3);
+        test_assert!(uptime_ns().is_some());
+
+        let start = hpet.counter();
+        let mut end = start;
+        for _ in 0..1_000_000 {
+            end = hpet.counter();
+            if end != start {
+                break;
+            }
+        }
+        test_assert!(end > start);
+
+        test_assert_eq!(
+            Err(HpetError::NoSuchComparator),
+            hpet.comparator(hpet.comparator_count()).map(|_| ())
+        );
+
+        let comparator = hpet.comparator(2).unwrap();
+        test_assert!(!comparator.poll());
+        test_assert_eq!(
+            Ok(()),
+            comparator.start_one_shot(Duration::from_micros(100))
+        );
+        let deadline = hpet.counter() + hpet.ticks(Duration::from_millis(100)).unwrap();
+        while !comparator.poll() && hpet.counter() < deadline {}
+        test_assert!(hpet.counter() < deadline);
+        comparator.stop();
+
+        let comparator = hpet.comparator(0).unwrap();
+        if comparator.supports_periodic() {
+            test_assert_eq!(
+                Ok(()),
+                comparator.start_periodic(Duration::from_micros(100))
+            );
+            for _ in 0..3 {
+                let deadline = hpet.counter() + hpet.ticks(Duration::from_millis(100)).unwrap();
+                while !comparator.poll() && hpet.counter() < deadline {}
+                test_assert!(hpet.counter() < deadline);
+            }
+            comparator.stop();
+        }
+    });
+}
@@ -1,4 +1,7 @@
-use crate::interrupt::{InterruptFrame, InterruptLookup, INTERRUPT_LOOKUP};
+use crate::{
+    gdt::SegmentSelector,
+    interrupt::{InterruptFrame, InterruptLookup, INTERRUPT_LOOKUP},
+};
 use core::{arch::asm, cell::RefCell};
 
 const NUM_GATE_DESC: usize = 256;
@@ -39,8 +42,8 @@ macro_rules! register_default_handler {
                     paste::paste! {
                         #[no_mangle]
                         #[allow(clippy::not_unsafe_ptr_arg_deref)]
-                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (_frame: $crate::interrupt::InterruptFrame) {
-                            $crate::interrupt::interrupt_entry($entry);
+                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (frame: $crate::interrupt::InterruptFrame) {
+                            $crate::interrupt::interrupt_entry($entry, &frame);
                         }
 
                         [<__default_handler $entry>] as u32
@@ -71,8 +74,8 @@ macro_rules! register_err_code_handler {
                     paste::paste! {
                         #[no_mangle]
                         #[allow(clippy::not_unsafe_ptr_arg_deref)]
-                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (_frame: $crate::interrupt::InterruptFrame, _err: u32) {
-                            $crate::interrupt::interrupt_entry($entry);
+                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (frame: $crate::interrupt::InterruptFrame, _err: u32) {
+                            $crate::interrupt::interrupt_entry($entry, &frame);
                         }
 
                         [<__default_handler $entry>] as u32
@@ -103,8 +106,8 @@ macro_rules! register_pic_handler {
                     paste::paste! {
                         #[no_mangle]
                         #[allow(clippy::not_unsafe_ptr_arg_deref)]
-                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (_frame: $crate::interrupt::InterruptFrame) {
-                            $crate::interrupt::interrupt_entry($entry);
+                        pub extern "x86-interrupt" fn [<__default_handler $entry>] (frame: $crate::interrupt::InterruptFrame) {
+                            $crate::interrupt::interrupt_entry($entry, &frame);
                             unsafe {
                                 let pic1 = $crate::port::Port::new(0x20);
                                 let pic2 = $crate::port::Port::new(0xA0);
@@ -136,40 +139,46 @@ macro_rules! register_pic_handlers {
 
 fn init_idt() {
     register_handlers!(
-        0, 1, 2, 3, 4, 5, 6, 7, 9, 15, 16, 19, 20, 22, 23, 24, 25, 26, 27, 28, 31, 48, 49, 50, 51,
-        52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74,
-        75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97,
-        98, 99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115,
-        116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 128, 129, 130, 131, 132, 133,
-        134, 135, 136, 137, 138, 139, 140, 141, 142, 143, 144, 145, 146, 147, 148, 149, 150, 151,
-        152, 153, 154, 155, 156, 157, 158, 159, 160, 161, 162, 163, 164, 165, 166, 167, 168, 169,
-        170, 171, 172, 173, 174, 175, 176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187,
-        188, 189, 190, 191, 192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205,
-        206, 207, 208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223,
-        224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239, 240, 241,
-        242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255
+        0, 1, 3, 4, 5, 6, 7, 9, 15, 16, 19, 20, 22, 23, 24, 25, 26, 27, 28, 31, 48, 49, 50, 51, 52,
+        53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75,
+        76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98,
+        99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116,
+        117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 128, 129, 130, 131, 132, 133, 134,
+        135, 136, 137, 138, 139, 140, 141, 142, 143, 144, 145, 146, 147, 148, 149, 150, 151, 152,
+        153, 154, 155, 156, 157, 158, 159, 160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170,
+        171, 172, 173, 174, 175, 176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187, 188,
+        189, 190, 191, 192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205, 206,
+        207, 208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223, 224,
+        225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239, 240, 241, 242,
+        243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255
     );
 
     register_err_code_handlers!(10, 11, 12, 13, 14, 17, 21, 29, 30);
 
     register_pic_handlers!(32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47);
 
+    // Switches to the double fault task, see `crate::tss`
+    IDT.set_entry(
+        GateDescriptor::new(0, SegmentSelector::DOUBLE_FAULT_TSS, GateType::Task),
+        8,
+    );
+
     IDT.set_entry(
         GateDescriptor::new(
             #[allow(clippy::fn_to_numeric_cast)]
             {
                 #[no_mangle]
                 #[allow(clippy::not_unsafe_ptr_arg_deref)]
-                pub extern "x86-interrupt" fn double_fault(_frame: InterruptFrame, _err: u32) {
-                    panic!("double fault");
+                pub extern "x86-interrupt" fn non_maskable_interrupt(frame: InterruptFrame) {
+                    crate::nmi::handle_nmi(&frame);
                 }
 
-                double_fault as u32
+                non_maskable_interrupt as u32
             },
             SegmentSelector::GDT_CODE,
             GateType::Interrupt32,
         ),
-        8,
+        2,
     );
 
     IDT.set_entry(
@@ -238,6 +247,100 @@ impl InterruptTable {
         debug_assert!(entry < NUM_GATE_DESC);
         self.entries.borrow_mut()[entry] = descriptor;
     }
+
+    fn entry(&self, entry: usize) -> GateDescriptor {
+        self.entries.borrow()[entry]
+    }
+}
+
+/// Reconfigures the gate for `vector`, starting from its current handler, selector, type and
+/// privilege level.
+///
+/// ```ignore
+/// // Allow `int 0x80` from ring 3, without masking interrupts in the handler
+/// idt::gate(0x80).trap().privilege(PrivilegeLevel::Ring3).install();
+/// ```
+pub fn gate(vector: u8) -> GateBuilder {
+    GateBuilder::from_descriptor(vector, IDT.entry(vector as usize))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GateBuilder {
+    vector: u8,
+    isr_offset: u32,
+    selector: SegmentSelector,
+    gate_type: GateType,
+    dpl: PrivilegeLevel,
+}
+
+impl GateBuilder {
+    fn from_descriptor(vector: u8, descriptor: GateDescriptor) -> Self {
+        if !descriptor.present() {
+            return Self {
+                vector,
+                isr_offset: 0,
+                selector: SegmentSelector::GDT_CODE,
+                gate_type: GateType::Interrupt32,
+                dpl: PrivilegeLevel::Ring0,
+            };
+        }
+
+        Self {
+            vector,
+            isr_offset: descriptor.offset(),
+            selector: SegmentSelector::from_value(descriptor.selector()),
+            gate_type: GateType::from_value(descriptor.gate_type())
+                .unwrap_or(GateType::Interrupt32),
+            dpl: PrivilegeLevel::from_value(descriptor.dpl()),
+        }
+    }
+
+    /// Interrupts are disabled on entry.
+    pub fn interrupt(mut self) -> Self {
+        self.gate_type = GateType::Interrupt32;
+        self
+    }
+
+    /// Interrupts are left enabled on entry.
+    pub fn trap(mut self) -> Self {
+        self.gate_type = GateType::Trap32;
+        self
+    }
+
+    /// Switches to the task described by the TSS at `tss_selector` in the GDT.
+    pub fn task(mut self, tss_selector: SegmentSelector) -> Self {
+        self.gate_type = GateType::Task;
+        self.selector = tss_selector;
+        self.isr_offset = 0;
+        self
+    }
+
+    pub fn gate_type(mut self, gate_type: GateType) -> Self {
+        self.gate_type = gate_type;
+        self
+    }
+
+    pub fn privilege(mut self, dpl: PrivilegeLevel) -> Self {
+        self.dpl = dpl;
+        self
+    }
+
+    /// Points the gate at `isr`, which must be an `extern "x86-interrupt"` function.
+    pub fn handler(mut self, isr: u32) -> Self {
+        self.isr_offset = isr;
+        self.selector = SegmentSelector::GDT_CODE;
+        self
+    }
+
+    fn descriptor(&self) -> GateDescriptor {
+        GateDescriptor::with_privilege(self.isr_offset, self.selector, self.gate_type, self.dpl)
+    }
+
+    pub fn install(self) {
+        crate::interrupt::without_interrupts(|| {
+            IDT.set_entry(self.descriptor(), self.vector as usize)
+        });
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -250,14 +353,23 @@ impl GateDescriptor {
     }
 
     pub const fn new(isr_offset: u32, selector: SegmentSelector, gate_type: GateType) -> Self {
-        let dpl = 0;
+        Self::with_privilege(isr_offset, selector, gate_type, PrivilegeLevel::Ring0)
+    }
 
+    /// `dpl` is the lowest privilege allowed to invoke the gate with `int`. Hardware interrupts
+    /// and exceptions ignore it.
+    pub const fn with_privilege(
+        isr_offset: u32,
+        selector: SegmentSelector,
+        gate_type: GateType,
+        dpl: PrivilegeLevel,
+    ) -> Self {
         let mut slf = Self::null();
         Self::write_entry(
             isr_offset,
             selector,
             gate_type,
-            dpl,
+            dpl.value(),
             &mut slf as *mut Self as *mut u8,
         );
 
@@ -318,43 +430,9 @@ impl GateDescriptor {
     }
 }
 
-/// https://wiki.osdev.org/Segment_Selector
-#[derive(Debug, Clone, Copy)]
-struct SegmentSelector(u16);
-
-#[allow(unused)]
-impl SegmentSelector {
-    pub const GDT_DATA: Self = Self::new(GdtIndex::Data);
-    pub const GDT_CODE: Self = Self::new(GdtIndex::Code);
-
-    pub const fn new(index: GdtIndex) -> Self {
-        Self(index.value() << 3)
-    }
-
-    pub const fn encoded_value(&self) -> u16 {
-        self.0
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-#[allow(unused)]
-enum GdtIndex {
-    Code,
-    Data,
-}
-
-impl GdtIndex {
-    pub const fn value(&self) -> u16 {
-        match self {
-            Self::Code => 1,
-            Self::Data => 2,
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(unused)]
-enum GateType {
+pub enum GateType {
     Task,
     Interrupt16,
     Trap16,
@@ -372,6 +450,46 @@ impl GateType {
             Self::Trap32 => 0b1111,
         }
     }
+
+    pub const fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0b0101 => Some(Self::Task),
+            0b0110 => Some(Self::Interrupt16),
+            0b0111 => Some(Self::Trap16),
+            0b1110 => Some(Self::Interrupt32),
+            0b1111 => Some(Self::Trap32),
+            _ => None,
+        }
+    }
+}
+
+/// https://wiki.osdev.org/Security#Rings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeLevel {
+    Ring0,
+    Ring1,
+    Ring2,
+    Ring3,
+}
+
+impl PrivilegeLevel {
+    pub const fn value(&self) -> u8 {
+        match self {
+            Self::Ring0 => 0,
+            Self::Ring1 => 1,
+            Self::Ring2 => 2,
+            Self::Ring3 => 3,
+        }
+    }
+
+    pub const fn from_value(value: u8) -> Self {
+        match value & 0b11 {
+            0 => Self::Ring0,
+            1 => Self::Ring1,
+            2 => Self::Ring2,
+            _ => Self::Ring3,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -391,19 +509,85 @@ mod tests {
         isr_offset: u32,
         selector: SegmentSelector,
         gate_type: GateType,
+        dpl: PrivilegeLevel,
     ) -> TestResult {
-        let gate = GateDescriptor::new(isr_offset, selector, gate_type);
+        let gate = GateDescriptor::with_privilege(isr_offset, selector, gate_type, dpl);
         debug!("{:#x}, {:#x}", gate.offset(), isr_offset);
         test_assert_eq!(gate.offset(), isr_offset);
         test_assert_eq!(gate.selector(), selector.encoded_value());
         test_assert_eq!(gate.gate_type(), gate_type.value());
+        test_assert_eq!(gate.dpl(), dpl.value());
         test_assert!(gate.present());
         TestResult::Success
     }
 
     test_case!(idt_descriptors, {
-        test_descriptor(0, SegmentSelector::GDT_DATA, GateType::Interrupt32);
-        test_descriptor(0xdeafdeaf, SegmentSelector::GDT_DATA, GateType::Task);
-        test_descriptor(0xd2203122, SegmentSelector::GDT_DATA, GateType::Task);
+        let ring0 = PrivilegeLevel::Ring0;
+        test_assert!(
+            test_descriptor(0, SegmentSelector::GDT_DATA, GateType::Interrupt32, ring0)
+                == TestResult::Success
+        );
+        test_assert!(
+            test_descriptor(0xdeafdeaf, SegmentSelector::GDT_DATA, GateType::Task, ring0)
+                == TestResult::Success
+        );
+        test_assert!(
+            test_descriptor(0xd2203122, SegmentSelector::GDT_DATA, GateType::Task, ring0)
+                == TestResult::Success
+        );
+
+        let ring3 = PrivilegeLevel::Ring3;
+        test_assert!(
+            test_descriptor(0xc0ffee, SegmentSelector::GDT_CODE, GateType::Trap32, ring3)
+                == TestResult::Success
+        );
+        test_assert!(
+            test_descriptor(
+                0x1234,
+                SegmentSelector::GDT_CODE,
+                GateType::Interrupt32,
+                ring3
+            ) == TestResult::Success
+        );
+        test_assert!(
+            test_descriptor(0, SegmentSelector::DOUBLE_FAULT_TSS, GateType::Task, ring0)
+                == TestResult::Success
+        );
+        test_assert_eq!(
+            GateDescriptor::new(0, SegmentSelector::GDT_CODE, GateType::Trap32).dpl(),
+            0
+        );
+    });
+
+    test_case!(idt_gate_builder, {
+        const VECTOR: u8 = 0xFF;
+        let previous = IDT.entry(VECTOR as usize);
+
+        gate(VECTOR)
+            .handler(0xdeadbeef)
+            .trap()
+            .privilege(PrivilegeLevel::Ring3)
+            .install();
+        let entry = IDT.entry(VECTOR as usize);
+        test_assert_eq!(0xdeadbeef, entry.offset());
+        test_assert_eq!(GateType::Trap32.value(), entry.gate_type());
+        test_assert_eq!(3, entry.dpl());
+
+        // Unchanged fields are carried over from the installed entry
+        gate(VECTOR).interrupt().install();
+        let entry = IDT.entry(VECTOR as usize);
+        test_assert_eq!(0xdeadbeef, entry.offset());
+        test_assert_eq!(GateType::Interrupt32.value(), entry.gate_type());
+        test_assert_eq!(3, entry.dpl());
+
+        gate(VECTOR)
+            .task(SegmentSelector::DOUBLE_FAULT_TSS)
+            .install();
+        let entry = IDT.entry(VECTOR as usize);
+        test_assert_eq!(0, entry.offset());
+        test_assert_eq!(0x20, entry.selector());
+        test_assert_eq!(GateType::Task.value(), entry.gate_type());
+
+        IDT.set_entry(previous, VECTOR as usize);
     });
 }
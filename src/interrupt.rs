@@ -1,4 +1,7 @@
-use crate::{lock::spinlock::SpinLock, pic::Pic, warn};
+use crate::{
+    interrupt_stats::INTERRUPT_STATS, interrupt_trace::INTERRUPT_TRACE, lock::spinlock::SpinLock,
+    nmi, pic::Pic, tsc, warn,
+};
 use alloc::boxed::Box;
 use core::fmt::Debug;
 use lazy_static::lazy_static;
@@ -37,6 +40,35 @@ impl InterruptGuard {
     }
 }
 
+/// Returns whether the interrupt flag is set in `EFLAGS`.
+pub fn interrupts_enabled() -> bool {
+    let flags: u32;
+    unsafe {
+        core::arch::asm!("pushfd", "pop {}", out(reg) flags, options(preserves_flags));
+    }
+    flags & (1 << 9) != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous interrupt flag afterwards.
+///
+/// Unlike [`InterruptGuard::run`], this is safe to call from within an interrupt handler.
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = interrupts_enabled();
+    if enabled {
+        unsafe { core::arch::asm!("cli") };
+    }
+    let ret = f();
+    if enabled {
+        unsafe { core::arch::asm!("sti") };
+    }
+    ret
+}
+
+/// Halts the CPU until the next interrupt. Never returns if interrupts are disabled.
+pub fn halt() {
+    unsafe { core::arch::asm!("hlt", options(nomem, nostack, preserves_flags)) };
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct InterruptFrame {
@@ -64,12 +96,19 @@ impl Debug for InterruptFrame {
     }
 }
 
-pub fn interrupt_entry(irq: u8) {
+pub fn interrupt_entry(irq: u8, frame: &InterruptFrame) {
+    let start = tsc::read();
+    if (Pic::VEC_OFFSET..Pic::VEC_OFFSET + 16).contains(&(irq as usize)) {
+        nmi::touch_watchdog();
+    }
     if let Some(handler) = INTERRUPT_LOOKUP.funcs.lock().get_mut(&irq) {
         handler.run();
     } else {
         warn!("interrupt {} not handled", irq);
     }
+    let end = tsc::read();
+    INTERRUPT_STATS.record(irq, start, end);
+    INTERRUPT_TRACE.record(irq, frame.ip, start, end);
 }
 
 lazy_static! {
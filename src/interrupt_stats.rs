@@ -0,0 +1,276 @@
+use crate::{info, interrupt::IrqId, pic::Pic, warn};
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+
+const NUM_VECTORS: usize = 256;
+
+pub static INTERRUPT_STATS: InterruptStats = InterruptStats::new();
+
+/// Per-vector interrupt accounting, updated by [`crate::interrupt::interrupt_entry`].
+///
+/// All timestamps and durations are in time stamp counter cycles.
+///
+/// PIC lines that fire more than `storm_threshold` times within `storm_window` cycles are masked
+/// until they are unmasked again through [`Pic::unmask`].
+pub struct InterruptStats {
+    vectors: [VectorStats; NUM_VECTORS],
+    storm_threshold: AtomicU32,
+    storm_window: AtomicU64,
+    /// Masks a line if the flag is set, unmasks it otherwise.
+    mask_line: fn(IrqId, bool),
+}
+
+impl InterruptStats {
+    pub const DEFAULT_STORM_THRESHOLD: u32 = 20_000;
+    pub const DEFAULT_STORM_WINDOW: u64 = 1 << 30;
+
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self::with_mask_line(|irq_id, masked| unsafe {
+            if masked {
+                Pic::mask_line(irq_id)
+            } else {
+                Pic::unmask_line(irq_id)
+            }
+        })
+    }
+
+    /// Masks and unmasks storming lines through `mask_line` instead of the PIC.
+    pub const fn with_mask_line(mask_line: fn(IrqId, bool)) -> Self {
+        Self {
+            vectors: [const { VectorStats::new() }; NUM_VECTORS],
+            storm_threshold: AtomicU32::new(Self::DEFAULT_STORM_THRESHOLD),
+            storm_window: AtomicU64::new(Self::DEFAULT_STORM_WINDOW),
+            mask_line,
+        }
+    }
+
+    /// Records one interrupt on `vector` whose handler ran from `start` to `end`.
+    pub fn record(&self, vector: u8, start: u64, end: u64) {
+        let stats = &self.vectors[vector as usize];
+        let duration = end.saturating_sub(start);
+
+        stats.count.fetch_add(1, Ordering::Relaxed);
+        stats.last_fired.store(start, Ordering::Relaxed);
+        stats.handler_cycles.fetch_add(duration, Ordering::Relaxed);
+        stats
+            .max_handler_cycles
+            .fetch_max(duration, Ordering::Relaxed);
+
+        if let Some(irq_id) = pic_irq(vector) {
+            self.detect_storm(vector, irq_id, start);
+        }
+    }
+
+    fn detect_storm(&self, vector: u8, irq_id: IrqId, now: u64) {
+        let stats = &self.vectors[vector as usize];
+
+        let window_start = stats.window_start.load(Ordering::Relaxed);
+        if now.saturating_sub(window_start) > self.storm_window.load(Ordering::Relaxed) {
+            stats.window_start.store(now, Ordering::Relaxed);
+            stats.window_count.store(1, Ordering::Relaxed);
+            return;
+        }
+
+        let window_count = stats.window_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if window_count > self.storm_threshold.load(Ordering::Relaxed)
+            && !stats.masked.swap(true, Ordering::Relaxed)
+        {
+            stats.storms.fetch_add(1, Ordering::Relaxed);
+            (self.mask_line)(irq_id, true);
+            warn!(
+                "interrupt storm on vector {} ({:?}): {} interrupts in {} cycles, masking line",
+                vector,
+                irq_id,
+                window_count,
+                now.saturating_sub(window_start)
+            );
+        }
+    }
+
+    /// Configures storm detection to trigger after more than `max_interrupts` within `window`
+    /// cycles.
+    pub fn set_storm_threshold(&self, max_interrupts: u32, window: u64) {
+        self.storm_threshold
+            .store(max_interrupts, Ordering::Relaxed);
+        self.storm_window.store(window, Ordering::Relaxed);
+    }
+
+    /// Returns a bitmask of the PIC lines masked due to an interrupt storm, where bit `n` is irq
+    /// `n` and bits 8-15 belong to the slave PIC.
+    pub fn storm_masked_lines(&self) -> u16 {
+        (0..16)
+            .filter(|line| {
+                self.vectors[Pic::VEC_OFFSET + line]
+                    .masked
+                    .load(Ordering::Relaxed)
+            })
+            .fold(0, |mask, line| mask | (1 << line))
+    }
+
+    /// Forgets that `irq_id` was masked due to a storm, unmasking the line again.
+    pub fn clear_storm(&self, irq_id: IrqId) {
+        let line = match irq_id {
+            IrqId::Pic1(line) => line as usize,
+            IrqId::Pic2(line) => line as usize + 8,
+        };
+        let stats = &self.vectors[Pic::VEC_OFFSET + line];
+        stats.window_count.store(0, Ordering::Relaxed);
+        // A storm only happens on an unmasked line
+        if stats.masked.swap(false, Ordering::Relaxed) {
+            (self.mask_line)(irq_id, false);
+        }
+    }
+
+    pub fn vector(&self, vector: u8) -> VectorSnapshot {
+        let stats = &self.vectors[vector as usize];
+        VectorSnapshot {
+            vector,
+            count: stats.count.load(Ordering::Relaxed),
+            last_fired: stats.last_fired.load(Ordering::Relaxed),
+            handler_cycles: stats.handler_cycles.load(Ordering::Relaxed),
+            max_handler_cycles: stats.max_handler_cycles.load(Ordering::Relaxed),
+            storms: stats.storms.load(Ordering::Relaxed),
+            masked: stats.masked.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Iterates over every vector that has fired at least once.
+    pub fn iter(&self) -> impl Iterator<Item = VectorSnapshot> + '_ {
+        (0..NUM_VECTORS)
+            .map(|vector| self.vector(vector as u8))
+            .filter(|snapshot| snapshot.count > 0)
+    }
+
+    pub fn log(&self) {
+        for snapshot in self.iter() {
+            info!("{}", snapshot);
+        }
+    }
+}
+
+fn pic_irq(vector: u8) -> Option<IrqId> {
+    let line = (vector as usize).checked_sub(Pic::VEC_OFFSET)?;
+    match line {
+        0..8 => Some(IrqId::Pic1(line as u8)),
+        8..16 => Some(IrqId::Pic2(line as u8 - 8)),
+        _ => None,
+    }
+}
+
+struct VectorStats {
+    count: AtomicU64,
+    last_fired: AtomicU64,
+    handler_cycles: AtomicU64,
+    max_handler_cycles: AtomicU64,
+    window_start: AtomicU64,
+    window_count: AtomicU32,
+    storms: AtomicU32,
+    masked: AtomicBool,
+}
+
+impl VectorStats {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            last_fired: AtomicU64::new(0),
+            handler_cycles: AtomicU64::new(0),
+            max_handler_cycles: AtomicU64::new(0),
+            window_start: AtomicU64::new(0),
+            window_count: AtomicU32::new(0),
+            storms: AtomicU32::new(0),
+            masked: AtomicBool::new(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorSnapshot {
+    pub vector: u8,
+    pub count: u64,
+    pub last_fired: u64,
+    pub handler_cycles: u64,
+    pub max_handler_cycles: u64,
+    pub storms: u32,
+    pub masked: bool,
+}
+
+impl VectorSnapshot {
+    pub fn average_handler_cycles(&self) -> u64 {
+        self.handler_cycles.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+impl Display for VectorSnapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "vector {:>3}: count {:>8}, last fired {:>14}, avg {:>8} cycles, max {:>8} cycles, storms {}{}",
+            self.vector,
+            self.count,
+            self.last_fired,
+            self.average_handler_cycles(),
+            self.max_handler_cycles,
+            self.storms,
+            if self.masked { " [masked]" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+    use core::sync::atomic::AtomicU16;
+
+    test_case!(interrupt_storm, {
+        static MASKED: AtomicU16 = AtomicU16::new(0);
+        // Too large for the boot stack
+        static STATS: InterruptStats = InterruptStats::with_mask_line(|irq_id, masked| {
+            let IrqId::Pic2(line) = irq_id else {
+                panic!("only IRQ 15 storms");
+            };
+            let bit = 1 << (line + 8);
+            if masked {
+                MASKED.fetch_or(bit, Ordering::Relaxed);
+            } else {
+                MASKED.fetch_and(!bit, Ordering::Relaxed);
+            }
+        });
+        let stats = &STATS;
+        stats.set_storm_threshold(4, 1000);
+
+        // Spread out interrupts never storm
+        for i in 0..10 {
+            stats.record(0x28, i * 2000, i * 2000 + 10);
+        }
+        test_assert!(!stats.vector(0x28).masked);
+
+        // Exceptions are counted but never masked
+        for i in 0..10 {
+            stats.record(0x0E, 100_000 + i, 100_000 + i);
+        }
+        test_assert!(!stats.vector(0x0E).masked);
+        test_assert_eq!(10, stats.vector(0x0E).count);
+
+        let snapshot = stats.vector(0x28);
+        test_assert_eq!(10, snapshot.count);
+        test_assert_eq!(18_000, snapshot.last_fired);
+        test_assert_eq!(10, snapshot.average_handler_cycles());
+        test_assert_eq!(2, stats.iter().count());
+
+        for i in 0..10 {
+            stats.record(0x2F, 200_000 + i, 200_000 + i);
+        }
+        test_assert!(stats.vector(0x2F).masked);
+        test_assert_eq!(1, stats.vector(0x2F).storms);
+        test_assert_eq!(1 << 15, stats.storm_masked_lines());
+        test_assert_eq!(1 << 15, MASKED.load(Ordering::Relaxed));
+
+        stats.clear_storm(IrqId::Pic2(7));
+        test_assert_eq!(0, stats.storm_masked_lines());
+        test_assert_eq!(0, MASKED.load(Ordering::Relaxed));
+    });
+}
@@ -0,0 +1,162 @@
+use core::{
+    cell::UnsafeCell,
+    fmt::{Display, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+const TRACE_LEN: usize = 256;
+
+/// Only the bootstrap processor is running, so a single ring serves as the per-CPU trace.
+pub static INTERRUPT_TRACE: InterruptTrace = InterruptTrace::new();
+
+/// Ring of the most recent interrupts, filled by [`crate::interrupt::interrupt_entry`].
+///
+/// Recording only claims a slot and copies an entry, so it can stay enabled while chasing timing
+/// issues. The ring is dumped over serial on panic, and can be dumped at any point with
+/// [`InterruptTrace::dump`].
+pub struct InterruptTrace {
+    enabled: AtomicBool,
+    recorded: AtomicUsize,
+    entries: UnsafeCell<[TraceEntry; TRACE_LEN]>,
+}
+
+unsafe impl Sync for InterruptTrace {}
+
+impl InterruptTrace {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            recorded: AtomicUsize::new(0),
+            entries: UnsafeCell::new([TraceEntry::empty(); TRACE_LEN]),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    /// Records `vector` interrupting `ip`, with the handler running from `start` to `end` in time
+    /// stamp counter cycles.
+    pub fn record(&self, vector: u8, ip: u32, start: u64, end: u64) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // Claiming the slot first keeps an exception raised while recording from overwriting
+        // this entry.
+        let index = self.recorded.fetch_add(1, Ordering::AcqRel) % TRACE_LEN;
+        let entry = TraceEntry {
+            vector,
+            ip,
+            timestamp: start,
+            duration: end.saturating_sub(start),
+        };
+        unsafe { core::ptr::write_volatile(&mut (*self.entries.get())[index], entry) };
+    }
+
+    /// Total number of interrupts recorded, including those already overwritten.
+    pub fn recorded(&self) -> usize {
+        self.recorded.load(Ordering::Acquire)
+    }
+
+    /// Iterates over the retained entries, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = TraceEntry> + '_ {
+        let recorded = self.recorded();
+        let first = recorded.saturating_sub(TRACE_LEN);
+        (first..recorded)
+            .map(|i| unsafe { core::ptr::read_volatile(&(*self.entries.get())[i % TRACE_LEN]) })
+    }
+
+    /// Writes the retained entries to `writer`, e.g. a [`crate::serial::RawSerialWriter`].
+    pub fn dump(&self, writer: &mut impl Write) -> core::fmt::Result {
+        let recorded = self.recorded();
+        if recorded == 0 {
+            return Ok(());
+        }
+
+        writeln!(
+            writer,
+            "\ninterrupt trace, last {} of {} interrupts:",
+            recorded.min(TRACE_LEN),
+            recorded
+        )?;
+        for entry in self.entries() {
+            writeln!(writer, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub vector: u8,
+    pub ip: u32,
+    /// Time stamp counter at handler entry.
+    pub timestamp: u64,
+    /// Handler duration in time stamp counter cycles.
+    pub duration: u64,
+}
+
+impl TraceEntry {
+    const fn empty() -> Self {
+        Self {
+            vector: 0,
+            ip: 0,
+            timestamp: 0,
+            duration: 0,
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "[{:>16}] vector {:>3} ip {:#010x} took {} cycles",
+            self.timestamp, self.vector, self.ip, self.duration
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(interrupt_trace, {
+        static TRACE: InterruptTrace = InterruptTrace::new();
+        let trace = &TRACE;
+        test_assert_eq!(0, trace.entries().count());
+
+        trace.record(0x21, 0x1000, 10, 15);
+        trace.disable();
+        trace.record(0x21, 0x2000, 20, 25);
+        trace.enable();
+        test_assert_eq!(1, trace.recorded());
+        test_assert_eq!(
+            Some(TraceEntry {
+                vector: 0x21,
+                ip: 0x1000,
+                timestamp: 10,
+                duration: 5,
+            }),
+            trace.entries().next()
+        );
+
+        for i in 0..TRACE_LEN as u64 + 10 {
+            trace.record(0x20, i as u32, i, i + 1);
+        }
+        test_assert_eq!(TRACE_LEN + 11, trace.recorded());
+        test_assert_eq!(TRACE_LEN, trace.entries().count());
+        test_assert_eq!(Some(10), trace.entries().next().map(|entry| entry.ip));
+        test_assert_eq!(
+            Some(TRACE_LEN as u32 + 9),
+            trace.entries().last().map(|entry| entry.ip)
+        );
+    });
+}
@@ -1,49 +1,159 @@
 use crate::{
+    clock,
+    deferred::DEFERRED_WORK,
     framebuffer::*,
-    gdt, idt,
+    gdt,
+    hpet::Hpet,
+    idt,
     interrupt::{self, InterruptLookup},
+    keymap,
     multiboot::MultibootHeader,
+    nmi,
     pic::Pic,
+    pit::Pit,
     port::PortManager,
-    ps2::{KeyCode, KeyState, KeyboardInput, Ps2Keyboard},
-    time::Rtc,
+    ps2::{KeyCode, KeyState, KeyboardInput, Ps2Controller, Ps2Keyboard, Ps2Mouse},
+    speaker::Speaker,
+    time::{Cmos, Rtc, RtcRate},
+    timer, tsc,
 };
+use alloc::sync::Arc;
 
 #[allow(unused)]
 pub struct Kernel {
     interrupt_lookup: &'static InterruptLookup,
     port_manager: PortManager,
     pic: Pic,
+    pit: Pit,
+    cmos: Arc<Cmos>,
+    hpet: Option<Hpet>,
+    speaker: Speaker,
     frame_buf: FrameBuffer,
-    keyboard: Ps2Keyboard,
+    ps2: Option<Ps2Controller>,
+    keyboard: Option<Ps2Keyboard>,
+    mouse: Option<Ps2Mouse>,
 }
 
 impl Kernel {
+    /// Roughly a second on a 2 GHz CPU.
+    const WATCHDOG_PERIOD: u64 = 1 << 31;
+    const WATCHDOG_THRESHOLD: u32 = 5;
+    /// 50 ms at the default PIT frequency.
+    const TSC_CALIBRATION_PERIODS: u32 = 50;
+
     pub fn new(multiboot_header: &MultibootHeader, mut port_manager: PortManager) -> Self {
         interrupt::InterruptGuard::run(|| {
             gdt::init();
             let interrupt_lookup = idt::init();
             let mut pic = Pic::new(&mut port_manager);
 
-            Rtc::enable_irq(&mut port_manager, interrupt_lookup, &mut pic);
-            let keyboard = Ps2Keyboard::new(&mut port_manager, interrupt_lookup, &mut pic);
+            timer::init();
+            let pit = Pit::new(
+                &mut port_manager,
+                interrupt_lookup,
+                &mut pic,
+                Pit::DEFAULT_FREQUENCY,
+            );
+            match tsc::calibrate_with_pit(&pit, Self::TSC_CALIBRATION_PERIODS) {
+                Some(frequency) => {
+                    crate::info!(
+                        "TSC frequency {} kHz, invariant: {}",
+                        frequency / 1000,
+                        tsc::is_invariant()
+                    );
+                }
+                None => {
+                    crate::info!("TSC unavailable");
+                }
+            }
+            let cmos = Rtc::enable_irq(
+                &mut port_manager,
+                interrupt_lookup,
+                &mut pic,
+                RtcRate::DEFAULT,
+            );
+            let hpet = match Hpet::new() {
+                Ok(hpet) => {
+                    crate::info!(
+                        "HPET at {} kHz with {} comparators",
+                        hpet.frequency() / 1000,
+                        hpet.comparator_count()
+                    );
+                    Some(hpet)
+                }
+                Err(err) => {
+                    crate::info!("HPET unavailable: {:?}", err);
+                    None
+                }
+            };
+            let clock_source = clock::select_best_source();
+            crate::info!("clock source: {:?}", clock_source);
+            crate::info!("wall clock: {}", cmos.datetime());
+            let speaker = Speaker::new(&mut port_manager, &pit);
+            if let Some(name) = multiboot_header.cmdline_option("keymap") {
+                match keymap::by_name(name) {
+                    Some(layout) => {
+                        keymap::set_active(layout);
+                        crate::info!("keymap: {}", layout.name);
+                    }
+                    None => {
+                        crate::warn!("unknown keymap: {}", name);
+                    }
+                }
+            }
+            let ps2 = match Ps2Controller::new(&mut port_manager) {
+                Ok(ps2) => Some(ps2),
+                Err(err) => {
+                    crate::warn!("PS/2 controller unavailable: {:?}", err);
+                    None
+                }
+            };
+            let keyboard = ps2.as_ref().and_then(|ps2| {
+                match Ps2Keyboard::new(ps2, interrupt_lookup, &mut pic) {
+                    Ok(keyboard) => Some(keyboard),
+                    Err(err) => {
+                        crate::warn!("PS/2 keyboard unavailable: {:?}", err);
+                        None
+                    }
+                }
+            });
+            let mouse =
+                ps2.as_ref()
+                    .and_then(|ps2| match Ps2Mouse::new(ps2, interrupt_lookup, &mut pic) {
+                        Ok(mouse) => Some(mouse),
+                        Err(err) => {
+                            crate::warn!("PS/2 mouse unavailable: {:?}", err);
+                            None
+                        }
+                    });
             let frame_buf = FrameBuffer::new(multiboot_header);
 
+            if let Err(err) = nmi::WATCHDOG.start(Self::WATCHDOG_PERIOD, Self::WATCHDOG_THRESHOLD) {
+                crate::info!("NMI watchdog unavailable: {:?}", err);
+            }
+
             crate::info!("kernel initialized");
 
             Self {
                 port_manager,
                 interrupt_lookup,
                 pic,
+                pit,
+                cmos,
+                hpet,
+                speaker,
                 frame_buf,
+                ps2,
                 keyboard,
+                mouse,
             }
         })
     }
 
     pub fn run(&mut self) {
-        #[allow(clippy::empty_loop)]
-        loop {}
+        loop {
+            DEFERRED_WORK.run_pending();
+        }
     }
 
     pub fn square_demo(&mut self) {
@@ -79,14 +189,18 @@ impl Kernel {
                 yellow_rect.tl.y = 0;
             }
 
-            self.keyboard.read_input_with(|input: KeyboardInput| {
-                // crate::info!("reading: {:?}", input);
-                if input.state == KeyState::Pressed {
-                    last_key_pressed = Some(input.key_code);
-                } else if Some(input.key_code) == last_key_pressed {
-                    last_key_pressed = None;
-                }
-            });
+            DEFERRED_WORK.run_pending();
+
+            if let Some(keyboard) = &self.keyboard {
+                keyboard.read_input_with(|input: KeyboardInput| {
+                    // crate::info!("reading: {:?}", input);
+                    if input.state == KeyState::Pressed {
+                        last_key_pressed = Some(input.key_code);
+                    } else if Some(input.key_code) == last_key_pressed {
+                        last_key_pressed = None;
+                    }
+                });
+            }
 
             const PLAYER_SPEED: isize = 16;
             if let Some(last_key_pressed) = &last_key_pressed {
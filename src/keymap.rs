@@ -0,0 +1,341 @@
+use crate::ps2::{KeyCode, Modifiers};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Index into [`KEYMAPS`] of the layout used by [`crate::ps2::KeyTranslator`].
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+pub static KEYMAPS: [&Keymap; 4] = [&US, &UK, &DE, &DVORAK];
+
+pub fn active() -> &'static Keymap {
+    KEYMAPS[ACTIVE.load(Ordering::Acquire)]
+}
+
+/// Switches the layout of every keyboard. Returns `false` if `keymap` is not one of [`KEYMAPS`].
+pub fn set_active(keymap: &'static Keymap) -> bool {
+    match KEYMAPS.iter().position(|k| core::ptr::eq(*k, keymap)) {
+        Some(index) => {
+            ACTIVE.store(index, Ordering::Release);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Looks up a built-in layout by its name, e.g. `de` from the `keymap=de` command line option.
+pub fn by_name(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS
+        .iter()
+        .find(|keymap| keymap.name.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Characters of one physical key.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEntry {
+    key_code: KeyCode,
+    normal: char,
+    shifted: char,
+    alt_gr: Option<char>,
+}
+
+const fn key(key_code: KeyCode, normal: char, shifted: char) -> KeyEntry {
+    KeyEntry {
+        key_code,
+        normal,
+        shifted,
+        alt_gr: None,
+    }
+}
+
+const fn alt_gr(key_code: KeyCode, normal: char, shifted: char, alt_gr: char) -> KeyEntry {
+    KeyEntry {
+        key_code,
+        normal,
+        shifted,
+        alt_gr: Some(alt_gr),
+    }
+}
+
+/// Layout of the printable keys.
+///
+/// Scan codes are decoded into layout independent [`KeyCode`]s named after the key's position,
+/// a keymap then assigns characters to those positions. Keys a layout does not list produce the
+/// same characters as on [`US`]. Dead keys are not supported, they produce their own character.
+#[derive(Debug)]
+pub struct Keymap {
+    pub name: &'static str,
+    keys: &'static [KeyEntry],
+}
+
+impl Keymap {
+    fn entry(&self, key_code: KeyCode) -> Option<&'static KeyEntry> {
+        self.keys
+            .iter()
+            .chain(US.keys)
+            .find(|entry| entry.key_code == key_code)
+    }
+
+    /// Text produced by pressing `key_code` with `modifiers`, if any.
+    pub fn character(&self, key_code: KeyCode, modifiers: &Modifiers) -> Option<char> {
+        // AltGr is Ctrl+Alt on keyboards without it
+        let alt_gr = modifiers.alt_gr || (modifiers.alt && modifiers.control());
+        // Alt combinations are shortcuts, not text
+        if (modifiers.alt && !alt_gr) || modifiers.meta() {
+            return None;
+        }
+
+        let Some(entry) = self.entry(key_code) else {
+            return keypad_character(key_code, modifiers);
+        };
+
+        if alt_gr {
+            return entry.alt_gr;
+        }
+
+        if modifiers.control() {
+            // Ctrl+A to Ctrl+Z map to the control characters 1 to 26
+            return entry
+                .normal
+                .is_ascii_lowercase()
+                .then(|| (entry.normal as u8 - b'a' + 1) as char);
+        }
+
+        // Caps Lock only affects letters whose shifted form is their capital, so e.g. 'ß' stays
+        let shift = if entry.normal.to_uppercase().eq([entry.shifted]) {
+            modifiers.shift() != modifiers.caps_lock
+        } else {
+            modifiers.shift()
+        };
+        Some(if shift { entry.shifted } else { entry.normal })
+    }
+}
+
+/// Text produced by keys that do not depend on the layout, or `None`.
+fn keypad_character(key_code: KeyCode, modifiers: &Modifiers) -> Option<char> {
+    if modifiers.control() {
+        return None;
+    }
+
+    let character = match key_code {
+        KeyCode::Enter | KeyCode::NumpadEnter => '\n',
+        KeyCode::Tab => '\t',
+        KeyCode::Backspace => '\u{8}',
+        KeyCode::Escape => '\u{1b}',
+        KeyCode::NumpadDivide => '/',
+        KeyCode::NumpadMultiply => '*',
+        KeyCode::NumpadSubtract => '-',
+        KeyCode::NumpadAdd => '+',
+        // Without Num Lock the remaining keypad keys navigate
+        _ if !modifiers.num_lock => return None,
+        KeyCode::Numpad0 => '0',
+        KeyCode::Numpad1 => '1',
+        KeyCode::Numpad2 => '2',
+        KeyCode::Numpad3 => '3',
+        KeyCode::Numpad4 => '4',
+        KeyCode::Numpad5 => '5',
+        KeyCode::Numpad6 => '6',
+        KeyCode::Numpad7 => '7',
+        KeyCode::Numpad8 => '8',
+        KeyCode::Numpad9 => '9',
+        KeyCode::NumpadDecimal => '.',
+        _ => return None,
+    };
+    Some(character)
+}
+
+pub static US: Keymap = Keymap {
+    name: "us",
+    keys: &[
+        key(KeyCode::KeyA, 'a', 'A'),
+        key(KeyCode::KeyB, 'b', 'B'),
+        key(KeyCode::KeyC, 'c', 'C'),
+        key(KeyCode::KeyD, 'd', 'D'),
+        key(KeyCode::KeyE, 'e', 'E'),
+        key(KeyCode::KeyF, 'f', 'F'),
+        key(KeyCode::KeyG, 'g', 'G'),
+        key(KeyCode::KeyH, 'h', 'H'),
+        key(KeyCode::KeyI, 'i', 'I'),
+        key(KeyCode::KeyJ, 'j', 'J'),
+        key(KeyCode::KeyK, 'k', 'K'),
+        key(KeyCode::KeyL, 'l', 'L'),
+        key(KeyCode::KeyM, 'm', 'M'),
+        key(KeyCode::KeyN, 'n', 'N'),
+        key(KeyCode::KeyO, 'o', 'O'),
+        key(KeyCode::KeyP, 'p', 'P'),
+        key(KeyCode::KeyQ, 'q', 'Q'),
+        key(KeyCode::KeyR, 'r', 'R'),
+        key(KeyCode::KeyS, 's', 'S'),
+        key(KeyCode::KeyT, 't', 'T'),
+        key(KeyCode::KeyU, 'u', 'U'),
+        key(KeyCode::KeyV, 'v', 'V'),
+        key(KeyCode::KeyW, 'w', 'W'),
+        key(KeyCode::KeyX, 'x', 'X'),
+        key(KeyCode::KeyY, 'y', 'Y'),
+        key(KeyCode::KeyZ, 'z', 'Z'),
+        key(KeyCode::Digit1, '1', '!'),
+        key(KeyCode::Digit2, '2', '@'),
+        key(KeyCode::Digit3, '3', '#'),
+        key(KeyCode::Digit4, '4', '$'),
+        key(KeyCode::Digit5, '5', '%'),
+        key(KeyCode::Digit6, '6', '^'),
+        key(KeyCode::Digit7, '7', '&'),
+        key(KeyCode::Digit8, '8', '*'),
+        key(KeyCode::Digit9, '9', '('),
+        key(KeyCode::Digit0, '0', ')'),
+        key(KeyCode::Backquote, '`', '~'),
+        key(KeyCode::Minus, '-', '_'),
+        key(KeyCode::Equal, '=', '+'),
+        key(KeyCode::BracketLeft, '[', '{'),
+        key(KeyCode::BracketRight, ']', '}'),
+        key(KeyCode::Backslash, '\\', '|'),
+        key(KeyCode::IntlBackslash, '\\', '|'),
+        key(KeyCode::Semicolon, ';', ':'),
+        key(KeyCode::Quote, '\'', '"'),
+        key(KeyCode::Comma, ',', '<'),
+        key(KeyCode::Period, '.', '>'),
+        key(KeyCode::Slash, '/', '?'),
+        key(KeyCode::Space, ' ', ' '),
+    ],
+};
+
+pub static UK: Keymap = Keymap {
+    name: "uk",
+    keys: &[
+        alt_gr(KeyCode::KeyA, 'a', 'A', 'á'),
+        alt_gr(KeyCode::KeyE, 'e', 'E', 'é'),
+        key(KeyCode::Digit2, '2', '"'),
+        key(KeyCode::Digit3, '3', '£'),
+        alt_gr(KeyCode::Digit4, '4', '$', '€'),
+        alt_gr(KeyCode::Backquote, '`', '¬', '¦'),
+        key(KeyCode::Quote, '\'', '@'),
+        key(KeyCode::Backslash, '#', '~'),
+        key(KeyCode::IntlBackslash, '\\', '|'),
+    ],
+};
+
+pub static DE: Keymap = Keymap {
+    name: "de",
+    keys: &[
+        alt_gr(KeyCode::KeyE, 'e', 'E', '€'),
+        alt_gr(KeyCode::KeyM, 'm', 'M', 'µ'),
+        alt_gr(KeyCode::KeyQ, 'q', 'Q', '@'),
+        key(KeyCode::KeyY, 'z', 'Z'),
+        key(KeyCode::KeyZ, 'y', 'Y'),
+        key(KeyCode::Digit1, '1', '!'),
+        alt_gr(KeyCode::Digit2, '2', '"', '²'),
+        alt_gr(KeyCode::Digit3, '3', '§', '³'),
+        key(KeyCode::Digit4, '4', '$'),
+        key(KeyCode::Digit5, '5', '%'),
+        key(KeyCode::Digit6, '6', '&'),
+        alt_gr(KeyCode::Digit7, '7', '/', '{'),
+        alt_gr(KeyCode::Digit8, '8', '(', '['),
+        alt_gr(KeyCode::Digit9, '9', ')', ']'),
+        alt_gr(KeyCode::Digit0, '0', '=', '}'),
+        key(KeyCode::Backquote, '^', '°'),
+        alt_gr(KeyCode::Minus, 'ß', '?', '\\'),
+        key(KeyCode::Equal, '´', '`'),
+        key(KeyCode::BracketLeft, 'ü', 'Ü'),
+        alt_gr(KeyCode::BracketRight, '+', '*', '~'),
+        key(KeyCode::Backslash, '#', '\''),
+        alt_gr(KeyCode::IntlBackslash, '<', '>', '|'),
+        key(KeyCode::Semicolon, 'ö', 'Ö'),
+        key(KeyCode::Quote, 'ä', 'Ä'),
+        key(KeyCode::Comma, ',', ';'),
+        key(KeyCode::Period, '.', ':'),
+        key(KeyCode::Slash, '-', '_'),
+    ],
+};
+
+/// US Dvorak.
+pub static DVORAK: Keymap = Keymap {
+    name: "dvorak",
+    keys: &[
+        key(KeyCode::Minus, '[', '{'),
+        key(KeyCode::Equal, ']', '}'),
+        key(KeyCode::KeyQ, '\'', '"'),
+        key(KeyCode::KeyW, ',', '<'),
+        key(KeyCode::KeyE, '.', '>'),
+        key(KeyCode::KeyR, 'p', 'P'),
+        key(KeyCode::KeyT, 'y', 'Y'),
+        key(KeyCode::KeyY, 'f', 'F'),
+        key(KeyCode::KeyU, 'g', 'G'),
+        key(KeyCode::KeyI, 'c', 'C'),
+        key(KeyCode::KeyO, 'r', 'R'),
+        key(KeyCode::KeyP, 'l', 'L'),
+        key(KeyCode::BracketLeft, '/', '?'),
+        key(KeyCode::BracketRight, '=', '+'),
+        key(KeyCode::KeyA, 'a', 'A'),
+        key(KeyCode::KeyS, 'o', 'O'),
+        key(KeyCode::KeyD, 'e', 'E'),
+        key(KeyCode::KeyF, 'u', 'U'),
+        key(KeyCode::KeyG, 'i', 'I'),
+        key(KeyCode::KeyH, 'd', 'D'),
+        key(KeyCode::KeyJ, 'h', 'H'),
+        key(KeyCode::KeyK, 't', 'T'),
+        key(KeyCode::KeyL, 'n', 'N'),
+        key(KeyCode::Semicolon, 's', 'S'),
+        key(KeyCode::Quote, '-', '_'),
+        key(KeyCode::KeyZ, ';', ':'),
+        key(KeyCode::KeyX, 'q', 'Q'),
+        key(KeyCode::KeyC, 'j', 'J'),
+        key(KeyCode::KeyV, 'k', 'K'),
+        key(KeyCode::KeyB, 'x', 'X'),
+        key(KeyCode::KeyN, 'b', 'B'),
+        key(KeyCode::KeyM, 'm', 'M'),
+        key(KeyCode::Comma, 'w', 'W'),
+        key(KeyCode::Period, 'v', 'V'),
+        key(KeyCode::Slash, 'z', 'Z'),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(keymaps, {
+        let none = Modifiers::default();
+        let shift = Modifiers {
+            shift_left: true,
+            ..none
+        };
+        let alt_gr = Modifiers {
+            alt_gr: true,
+            ..none
+        };
+        let caps_lock = Modifiers {
+            caps_lock: true,
+            ..none
+        };
+        let control = Modifiers {
+            control_left: true,
+            ..none
+        };
+
+        test_assert_eq!(Some('y'), US.character(KeyCode::KeyY, &none));
+        test_assert_eq!(Some('z'), DE.character(KeyCode::KeyY, &none));
+        test_assert_eq!(Some('Ö'), DE.character(KeyCode::Semicolon, &caps_lock));
+        test_assert_eq!(Some('ß'), DE.character(KeyCode::Minus, &caps_lock));
+        test_assert_eq!(Some('@'), DE.character(KeyCode::KeyQ, &alt_gr));
+        test_assert_eq!(Some('{'), DE.character(KeyCode::Digit7, &alt_gr));
+        test_assert_eq!(None, DE.character(KeyCode::KeyW, &alt_gr));
+        // Unlisted keys fall back to US
+        test_assert_eq!(Some('W'), DE.character(KeyCode::KeyW, &shift));
+
+        test_assert_eq!(Some('£'), UK.character(KeyCode::Digit3, &shift));
+        test_assert_eq!(Some('@'), UK.character(KeyCode::Quote, &shift));
+        test_assert_eq!(Some('#'), UK.character(KeyCode::Backslash, &caps_lock));
+
+        test_assert_eq!(Some('\''), DVORAK.character(KeyCode::KeyQ, &none));
+        test_assert_eq!(Some('P'), DVORAK.character(KeyCode::KeyR, &shift));
+        // Shortcuts follow the layout
+        test_assert_eq!(Some('\u{3}'), DVORAK.character(KeyCode::KeyI, &control));
+
+        test_assert!(core::ptr::eq(&DE, by_name("DE").unwrap()));
+        test_assert!(by_name("fr").is_none());
+        test_assert!(core::ptr::eq(&US, active()));
+        test_assert!(set_active(&DVORAK));
+        test_assert!(core::ptr::eq(&DVORAK, active()));
+        set_active(&US);
+    });
+}
@@ -13,25 +13,39 @@ use port::PortManager;
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod channel;
 pub mod circular_buffer;
+pub mod clock;
 pub mod cpuuid;
+pub mod deferred;
 pub mod exit;
 pub mod framebuffer;
 pub mod gdt;
+pub mod hpet;
 pub mod idt;
 pub mod interrupt;
+pub mod interrupt_stats;
+pub mod interrupt_trace;
 pub mod kernel;
+pub mod keymap;
 pub mod lock;
 pub mod log;
 pub mod memory;
 pub mod multiboot;
+pub mod nmi;
 pub mod pic;
+pub mod pit;
 pub mod port;
 pub mod ps2;
 pub mod serial;
+pub mod speaker;
+pub mod system_control;
 pub mod test;
 pub mod time;
+pub mod timer;
+pub mod tsc;
+pub mod tss;
 pub mod vga;
 
 global_asm!(include_str!("boot.s"));
@@ -57,20 +71,9 @@ pub extern "C" fn kernel_main(magic: u32, multiboot_header: *const MultibootHead
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    struct PanicWriter;
-
-    impl core::fmt::Write for PanicWriter {
-        fn write_str(&mut self, s: &str) -> core::fmt::Result {
-            for c in s.chars() {
-                while unsafe { port::Port::new(0x3FD).read() & 0x20 } == 0 {}
-                unsafe { port::Port::new(0x3F8).write(c as u8) };
-            }
-            Ok(())
-        }
-    }
-
     use core::fmt::Write;
-    let _ = write!(PanicWriter, "\nPANIC: {}", info.message());
+    let _ = write!(serial::RawSerialWriter, "\nPANIC: {}", info.message());
+    let _ = interrupt_trace::INTERRUPT_TRACE.dump(&mut serial::RawSerialWriter);
 
     exit_qemu(QemuExitCode::Failed);
     loop {}
@@ -34,6 +34,27 @@ pub struct MultibootHeader {
     pub color_info: [u8; 5],
 }
 
+impl MultibootHeader {
+    /// The kernel command line passed by the boot loader, if any.
+    pub fn cmdline(&self) -> Option<&'static str> {
+        if self.flags & (1 << 2) == 0 || self.cmdline == 0 {
+            return None;
+        }
+
+        let cmdline = unsafe { core::ffi::CStr::from_ptr(self.cmdline as *const _) };
+        cmdline.to_str().ok()
+    }
+
+    /// Value of a `key=value` option on the kernel command line.
+    pub fn cmdline_option(&self, key: &str) -> Option<&'static str> {
+        self.cmdline()?
+            .split_ascii_whitespace()
+            .filter_map(|option| option.split_once('='))
+            .find(|(option_key, _)| *option_key == key)
+            .map(|(_, value)| value)
+    }
+}
+
 #[allow(unused)]
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
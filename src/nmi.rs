@@ -0,0 +1,311 @@
+use crate::{
+    cpuuid::{self, CpuidFeatureEdx},
+    interrupt::{self, InterruptFrame},
+    interrupt_trace::INTERRUPT_TRACE,
+    serial::RawSerialWriter,
+    system_control::{SYSTEM_CONTROL_A, SYSTEM_CONTROL_B},
+    time::Cmos,
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+
+static NMI_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables non-maskable interrupts.
+///
+/// The NMI disable bit shares port `0x70` with the CMOS register select, so the write goes through
+/// `cmos`, which preserves the state set here on every register access.
+///
+/// [`https://wiki.osdev.org/Non_Maskable_Interrupt`]
+pub fn enable(cmos: &Cmos) {
+    NMI_DISABLED.store(false, Ordering::Release);
+    write_nmi_bit(cmos);
+}
+
+pub fn disable(cmos: &Cmos) {
+    NMI_DISABLED.store(true, Ordering::Release);
+    write_nmi_bit(cmos);
+}
+
+pub fn is_enabled() -> bool {
+    !NMI_DISABLED.load(Ordering::Acquire)
+}
+
+/// The value of bit 7 of any write to port `0x70`.
+pub fn disable_bit() -> u8 {
+    (NMI_DISABLED.load(Ordering::Acquire) as u8) << 7
+}
+
+fn write_nmi_bit(cmos: &Cmos) {
+    // Select status register D, which is read only, and read it back so the RTC is not left in an
+    // undefined state.
+    let _ = cmos.read_register(0x0D);
+}
+
+/// Decoded reasons for an NMI.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NmiCause {
+    /// Port `0x92` bit 4.
+    pub watchdog_timer: bool,
+    /// Port `0x61` bit 6, channel check.
+    pub io_check: bool,
+    /// Port `0x61` bit 7, memory parity error.
+    pub memory_parity: bool,
+    /// The performance counter driving the [`Watchdog`] overflowed.
+    pub watchdog: bool,
+}
+
+impl NmiCause {
+    fn from_system_control(port_a: u8, port_b: u8) -> Self {
+        Self {
+            watchdog_timer: port_a & (1 << 4) != 0,
+            io_check: port_b & (1 << 6) != 0,
+            memory_parity: port_b & (1 << 7) != 0,
+            watchdog: false,
+        }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Entry point for vector 2.
+///
+/// An NMI can interrupt the logger while it holds its buffer, so diagnostics only go through
+/// [`RawSerialWriter`].
+pub fn handle_nmi(frame: &InterruptFrame) {
+    let mut cause = NmiCause::from_system_control(SYSTEM_CONTROL_A.read(), SYSTEM_CONTROL_B.read());
+    cause.watchdog = WATCHDOG.handle_overflow(frame);
+
+    if cause.io_check || cause.memory_parity {
+        let _ = writeln!(
+            RawSerialWriter,
+            "NMI at {:#x}: io check {}, memory parity {}",
+            frame.ip, cause.io_check, cause.memory_parity
+        );
+        // Pulse the clear bits to reset the latched error state, leaving the speaker bits alone.
+        SYSTEM_CONTROL_B.update(|control| control | 0x0C);
+        SYSTEM_CONTROL_B.update(|control| control & !0x0C);
+    }
+
+    if cause.watchdog_timer {
+        let _ = writeln!(
+            RawSerialWriter,
+            "NMI at {:#x}: watchdog timer status set",
+            frame.ip
+        );
+    }
+
+    if cause.is_unknown() {
+        let _ = writeln!(RawSerialWriter, "NMI at {:#x} with unknown cause", frame.ip);
+    }
+}
+
+/// Counts serviced maskable interrupts for the [`Watchdog`].
+pub fn touch_watchdog() {
+    WATCHDOG.heartbeat.fetch_add(1, Ordering::Relaxed);
+}
+
+pub static WATCHDOG: Watchdog = Watchdog::new();
+
+#[derive(Debug)]
+pub enum WatchdogError {
+    NoLocalApic,
+    NoPerformanceMonitoring,
+}
+
+/// Hard lockup detector.
+///
+/// The first general purpose performance counter counts unhalted core cycles and raises an NMI
+/// through the local APIC every `period` cycles. If no maskable interrupt was serviced for
+/// `threshold` consecutive NMIs, the CPU is considered stuck with interrupts disabled.
+///
+/// Requires architectural performance monitoring, which QEMU only provides with KVM.
+pub struct Watchdog {
+    running: AtomicBool,
+    version: AtomicU32,
+    period: AtomicU64,
+    threshold: AtomicU32,
+    heartbeat: AtomicU32,
+    last_heartbeat: AtomicU32,
+    stalled: AtomicU32,
+    reported: AtomicBool,
+}
+
+impl Watchdog {
+    const IA32_APIC_BASE: u32 = 0x1B;
+    const IA32_PMC0: u32 = 0xC1;
+    const IA32_PERFEVTSEL0: u32 = 0x186;
+    const IA32_PERF_GLOBAL_STATUS: u32 = 0x38E;
+    const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+    const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+    const APIC_SPURIOUS_VECTOR: usize = 0xF0;
+    const APIC_LVT_PERFMON: usize = 0x340;
+    /// Delivery mode NMI, unmasked.
+    const LVT_NMI: u32 = 0b100 << 8;
+
+    const fn new() -> Self {
+        Self {
+            running: AtomicBool::new(false),
+            version: AtomicU32::new(0),
+            period: AtomicU64::new(0),
+            threshold: AtomicU32::new(0),
+            heartbeat: AtomicU32::new(0),
+            last_heartbeat: AtomicU32::new(0),
+            stalled: AtomicU32::new(0),
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    pub fn start(&self, period: u64, threshold: u32) -> Result<(), WatchdogError> {
+        if !cpuuid::has_feature(CpuidFeatureEdx::APIC) || !cpuuid::has_feature(CpuidFeatureEdx::MSR)
+        {
+            return Err(WatchdogError::NoLocalApic);
+        }
+
+        // https://www.intel.com/content/www/us/en/developer/articles/technical/intel-sdm.html
+        // Volume 3, 20.2.1 Architectural Performance Monitoring Version 1
+        let max_leaf = cpuuid::cpuid(0x0).eax;
+        let perfmon = cpuuid::cpuid(0xA);
+        let version = perfmon.eax & 0xFF;
+        let counters = (perfmon.eax >> 8) & 0xFF;
+        // EBX bit 0 set means the unhalted core cycles event is unavailable
+        if max_leaf < 0xA || version == 0 || counters == 0 || perfmon.ebx & 1 != 0 {
+            return Err(WatchdogError::NoPerformanceMonitoring);
+        }
+
+        self.version.store(version, Ordering::Relaxed);
+        self.period.store(period, Ordering::Relaxed);
+        self.threshold.store(threshold, Ordering::Relaxed);
+        self.last_heartbeat
+            .store(self.heartbeat.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.stalled.store(0, Ordering::Relaxed);
+        self.reported.store(false, Ordering::Relaxed);
+
+        interrupt::without_interrupts(|| unsafe {
+            let apic_base = cpuuid::read_msr(Self::IA32_APIC_BASE);
+            cpuuid::write_msr(Self::IA32_APIC_BASE, apic_base | (1 << 11));
+
+            let spurious = Self::read_apic(Self::APIC_SPURIOUS_VECTOR);
+            Self::write_apic(Self::APIC_SPURIOUS_VECTOR, spurious | (1 << 8) | 0xFF);
+            Self::write_apic(Self::APIC_LVT_PERFMON, Self::LVT_NMI);
+
+            self.arm();
+            // Unhalted core cycles in ring 0 and 3, interrupt on overflow, enabled
+            cpuuid::write_msr(
+                Self::IA32_PERFEVTSEL0,
+                0x3C | (1 << 16) | (1 << 17) | (1 << 20) | (1 << 22),
+            );
+            if version >= 2 {
+                cpuuid::write_msr(Self::IA32_PERF_GLOBAL_CTRL, 1);
+            }
+        });
+
+        self.running.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if self.running.swap(false, Ordering::AcqRel) {
+            unsafe { cpuuid::write_msr(Self::IA32_PERFEVTSEL0, 0) };
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    unsafe fn arm(&self) {
+        // Writes to the counter are sign extended from 32 bits, which limits the period
+        let period = self.period.load(Ordering::Relaxed).min(i32::MAX as u64);
+        cpuuid::write_msr(Self::IA32_PMC0, (period as i64).wrapping_neg() as u64);
+    }
+
+    /// Returns whether the NMI was raised by the watchdog counter.
+    fn handle_overflow(&self, frame: &InterruptFrame) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+
+        // With version 1 there is no global status, an overflowed counter has wrapped around to a
+        // small positive value instead.
+        let overflowed = unsafe {
+            if self.version.load(Ordering::Relaxed) >= 2 {
+                let status = cpuuid::read_msr(Self::IA32_PERF_GLOBAL_STATUS) & 1 != 0;
+                cpuuid::write_msr(Self::IA32_PERF_GLOBAL_OVF_CTRL, 1);
+                status
+            } else {
+                (cpuuid::read_msr(Self::IA32_PMC0) as u32) < 0x8000_0000
+            }
+        };
+        if !overflowed {
+            return false;
+        }
+
+        unsafe {
+            self.arm();
+            // The LVT entry is masked on delivery
+            Self::write_apic(Self::APIC_LVT_PERFMON, Self::LVT_NMI);
+        }
+
+        let heartbeat = self.heartbeat.load(Ordering::Relaxed);
+        if heartbeat != self.last_heartbeat.swap(heartbeat, Ordering::Relaxed) {
+            self.stalled.store(0, Ordering::Relaxed);
+            self.reported.store(false, Ordering::Relaxed);
+            return true;
+        }
+
+        let stalled = self.stalled.fetch_add(1, Ordering::Relaxed) + 1;
+        if stalled >= self.threshold.load(Ordering::Relaxed)
+            && !self.reported.swap(true, Ordering::Relaxed)
+        {
+            let _ = writeln!(
+                RawSerialWriter,
+                "hard lockup: no interrupts serviced for {} watchdog periods, stuck at {:#x}",
+                stalled, frame.ip
+            );
+            let _ = writeln!(RawSerialWriter, "frame: {:#?}", frame);
+            let _ = INTERRUPT_TRACE.dump(&mut RawSerialWriter);
+        }
+
+        true
+    }
+
+    unsafe fn read_apic(register: usize) -> u32 {
+        let base = (cpuuid::read_msr(Self::IA32_APIC_BASE) & 0xFFFF_F000) as usize;
+        core::ptr::read_volatile((base + register) as *const u32)
+    }
+
+    unsafe fn write_apic(register: usize, value: u32) {
+        let base = (cpuuid::read_msr(Self::IA32_APIC_BASE) & 0xFFFF_F000) as usize;
+        core::ptr::write_volatile((base + register) as *mut u32, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{port::PortManager, test_case};
+
+    test_case!(nmi_cause, {
+        let cause = NmiCause::from_system_control(0, 0);
+        test_assert!(cause.is_unknown());
+
+        let cause = NmiCause::from_system_control(1 << 4, 1 << 7);
+        test_assert!(cause.watchdog_timer);
+        test_assert!(cause.memory_parity);
+        test_assert!(!cause.io_check);
+
+        let cmos = Cmos::new(&mut PortManager::default());
+        disable(&cmos);
+        test_assert!(!is_enabled());
+        test_assert_eq!(0x80, disable_bit());
+        enable(&cmos);
+        test_assert!(is_enabled());
+        test_assert_eq!(0, disable_bit());
+    });
+}
@@ -1,5 +1,6 @@
 use crate::{
     interrupt::IrqId,
+    interrupt_stats::INTERRUPT_STATS,
     port::{Port, PortManager},
 };
 
@@ -49,17 +50,56 @@ impl Pic {
             }
             IrqId::Pic2(offset) => self.smask &= !(1 << offset),
         }
+        INTERRUPT_STATS.clear_storm(index);
         self.remap();
     }
 
+    pub fn mask(&mut self, index: IrqId) {
+        match index {
+            IrqId::Pic1(offset) => {
+                // Prevent Pic2 from being masked
+                if offset != 2 {
+                    self.mmask |= 1 << offset
+                }
+            }
+            IrqId::Pic2(offset) => self.smask |= 1 << offset,
+        }
+        self.remap();
+    }
+
+    /// Masks `index` by modifying the interrupt mask register in place, without going through an
+    /// owned [`Pic`].
+    ///
+    /// Used from interrupt context, e.g. when silencing an interrupt storm.
+    pub unsafe fn mask_line(index: IrqId) {
+        let (port, offset) = match index {
+            IrqId::Pic1(offset) => (Port::new(0x21), offset),
+            IrqId::Pic2(offset) => (Port::new(0xA1), offset),
+        };
+        let mask = port.read();
+        port.write(mask | (1 << offset));
+    }
+
+    /// Unmasks a line masked by [`Self::mask_line`], without going through an owned [`Pic`].
+    pub unsafe fn unmask_line(index: IrqId) {
+        let (port, offset) = match index {
+            IrqId::Pic1(offset) => (Port::new(0x21), offset),
+            IrqId::Pic2(offset) => (Port::new(0xA1), offset),
+        };
+        let mask = port.read();
+        port.write(mask & !(1 << offset));
+    }
+
     pub fn remap(&mut self) {
         const INIT: u8 = 0x10;
         const ICW4: u8 = 0x01;
         const IC8086: u8 = 0x01;
 
         unsafe {
-            let master_mask = self.mmask;
-            let slave_mask = self.smask;
+            // Lines silenced by storm detection stay masked until explicitly unmasked
+            let storm_mask = INTERRUPT_STATS.storm_masked_lines();
+            let master_mask = self.mmask | (storm_mask as u8 & !(1 << 2));
+            let slave_mask = self.smask | (storm_mask >> 8) as u8;
 
             let master_offset = Self::VEC_OFFSET;
             let slave_offset = Self::VEC_OFFSET + 8;
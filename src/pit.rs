@@ -0,0 +1,209 @@
+use crate::{
+    interrupt::{self, InterruptHandler, InterruptLookup, IrqId, PicHandler},
+    pic::Pic,
+    port::{Port, PortManager},
+    timer,
+};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Channel 0 reload value, zero while the PIT is not running.
+static DIVISOR: AtomicU32 = AtomicU32::new(0);
+/// Tick count and uptime at the last change of frequency.
+static EPOCH_TICKS: AtomicU64 = AtomicU64::new(0);
+static EPOCH_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of channel 0 interrupts since the PIT was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Acquire)
+}
+
+/// Time since the PIT was started in nanoseconds, or `None` if it is not running.
+pub fn uptime_ns() -> Option<u64> {
+    match DIVISOR.load(Ordering::Acquire) {
+        0 => None,
+        divisor => {
+            let ticks = ticks() - EPOCH_TICKS.load(Ordering::Acquire);
+            let cycles = ticks as u128 * divisor as u128;
+            let ns = (cycles * 1_000_000_000 / Pit::BASE_FREQUENCY as u128) as u64;
+            Some(EPOCH_NS.load(Ordering::Acquire) + ns)
+        }
+    }
+}
+
+/// Whether the PIT has been started and [`ticks`] is advancing.
+pub fn is_running() -> bool {
+    DIVISOR.load(Ordering::Acquire) != 0
+}
+
+/// Length of one tick in nanoseconds, or `None` if the PIT is not running.
+pub fn tick_period_ns() -> Option<u64> {
+    match DIVISOR.load(Ordering::Acquire) {
+        0 => None,
+        divisor => Some(divisor as u64 * 1_000_000_000 / Pit::BASE_FREQUENCY as u64),
+    }
+}
+
+/// Channel 0 of the 8253/8254 programmable interval timer, driving the global tick count.
+///
+/// [`https://wiki.osdev.org/Programmable_Interval_Timer`]
+pub struct Pit {
+    channel0: Port,
+    command: Port,
+    divisor: u32,
+}
+
+impl Pit {
+    /// Input clock of every channel in Hz.
+    pub const BASE_FREQUENCY: u32 = 1_193_182;
+    pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+    pub fn new(
+        port_manager: &mut PortManager,
+        interrupt_lookup: &InterruptLookup,
+        pic: &mut Pic,
+        frequency: u32,
+    ) -> Self {
+        let channel0 = unsafe {
+            port_manager
+                .request_port(0x40)
+                .expect("only one Pit driver may be active")
+        };
+        let command = unsafe {
+            port_manager
+                .request_port(0x43)
+                .expect("only one Pit driver may be active")
+        };
+
+        let mut slf = Self {
+            channel0,
+            command,
+            divisor: 0,
+        };
+        slf.set_frequency(frequency);
+
+        let pic_id = IrqId::Pic1(0);
+        interrupt_lookup.register_handler(InterruptHandler::Pic(PicHandler::new(
+            pic_id,
+            move || {
+                let ticks = TICKS.fetch_add(1, Ordering::AcqRel) + 1;
+                timer::on_tick(ticks);
+            },
+        )));
+        pic.unmask(pic_id);
+
+        slf
+    }
+
+    /// Reprograms channel 0 to fire as close to `frequency` Hz as the divisor allows.
+    pub fn set_frequency(&mut self, frequency: u32) {
+        let divisor = Self::divisor_for(frequency);
+
+        interrupt::without_interrupts(|| unsafe {
+            // Time elapsed at the old rate is kept, uptime continues at the new rate
+            EPOCH_NS.store(uptime_ns().unwrap_or(0), Ordering::Release);
+            EPOCH_TICKS.store(ticks(), Ordering::Release);
+
+            // Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary
+            self.command.write(0b0011_0100);
+            // A reload value of 0 is interpreted as 65536
+            self.channel0.write(divisor as u8);
+            self.channel0.write((divisor >> 8) as u8);
+
+            self.divisor = divisor;
+            DIVISOR.store(divisor, Ordering::Release);
+        });
+    }
+
+    /// The reload value closest to `frequency`, within `2..=65536`.
+    ///
+    /// A reload value of 1 is illegal in modes 2 and 3.
+    pub const fn divisor_for(frequency: u32) -> u32 {
+        if frequency == 0 {
+            return 65536;
+        }
+
+        let divisor = (Self::BASE_FREQUENCY + frequency / 2) / frequency;
+        if divisor < 2 {
+            2
+        } else if divisor > 65536 {
+            65536
+        } else {
+            divisor
+        }
+    }
+
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// The actual interrupt frequency in Hz, rounded down.
+    pub fn frequency(&self) -> u32 {
+        Self::BASE_FREQUENCY / self.divisor
+    }
+
+    /// Hands out channel 2, which drives the PC speaker. The command port stays with the `Pit`,
+    /// writes to it from either side are done with interrupts disabled.
+    pub fn channel2(&self, port_manager: &mut PortManager) -> Option<PitChannel2> {
+        let data = unsafe { port_manager.request_port(0x42)? };
+        let command = unsafe { Port::new(0x43) };
+        Some(PitChannel2 { data, command })
+    }
+
+    /// Latches and reads the current channel 0 count, which counts down from the divisor once per
+    /// [`Self::BASE_FREQUENCY`] period.
+    pub fn read_count(&self) -> u16 {
+        interrupt::without_interrupts(|| unsafe {
+            // Latch count value command for channel 0
+            self.command.write(0b0000_0000);
+            let low = self.channel0.read() as u16;
+            let high = self.channel0.read() as u16;
+            (high << 8) | low
+        })
+    }
+}
+
+/// Channel 2, whose output is gated by port `0x61` and feeds the PC speaker.
+pub struct PitChannel2 {
+    data: Port,
+    command: Port,
+}
+
+impl PitChannel2 {
+    /// Outputs a square wave as close to `frequency` Hz as the divisor allows.
+    pub fn set_square_wave(&mut self, frequency: u32) {
+        let divisor = Pit::divisor_for(frequency);
+        interrupt::without_interrupts(|| unsafe {
+            // Channel 2, lobyte/hibyte access, mode 3 (square wave generator), binary
+            self.command.write(0b1011_0110);
+            self.data.write(divisor as u8);
+            self.data.write((divisor >> 8) as u8);
+        });
+    }
+
+    pub fn read_count(&self) -> u16 {
+        interrupt::without_interrupts(|| unsafe {
+            // Latch count value command for channel 2
+            self.command.write(0b1000_0000);
+            let low = self.data.read() as u16;
+            let high = self.data.read() as u16;
+            (high << 8) | low
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(pit_divisor, {
+        test_assert_eq!(1193, Pit::divisor_for(1000));
+        test_assert_eq!(11932, Pit::divisor_for(100));
+        test_assert_eq!(65536, Pit::divisor_for(18));
+        test_assert_eq!(65536, Pit::divisor_for(0));
+        test_assert_eq!(2, Pit::divisor_for(Pit::BASE_FREQUENCY / 2));
+        test_assert_eq!(2, Pit::divisor_for(Pit::BASE_FREQUENCY));
+        test_assert_eq!(2, Pit::divisor_for(u32::MAX));
+    });
+}
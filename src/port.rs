@@ -1,8 +1,10 @@
 use arrayvec::ArrayVec;
 use core::{arch::asm, ops::Deref};
 
-const RESERVED_PORTS: [u16; 1] = [
+const RESERVED_PORTS: [u16; 3] = [
     0xF4, // Exit qemu
+    0x61, // Shared system control B, see `system_control::SYSTEM_CONTROL_B`
+    0x92, // Shared system control A
 ];
 
 /// Interface to port IO.
@@ -55,7 +57,7 @@ impl PortManager {
 pub struct Port(u16);
 
 impl Port {
-    pub unsafe fn new(port: u16) -> Self {
+    pub const unsafe fn new(port: u16) -> Self {
         Self(port)
     }
 
@@ -1,91 +1,1272 @@
 use crate::{
     circular_buffer::CircularBuffer,
+    clock::{Duration, Instant},
+    deferred::{TaskletHandle, DEFERRED_WORK},
     info,
-    interrupt::{InterruptHandler, InterruptLookup, IrqId, PicHandler},
+    interrupt::{self, InterruptHandler, InterruptLookup, IrqId, PicHandler},
+    keymap,
+    lock::spinlock::SpinLock,
     pic::Pic,
     port::{Port, PortManager},
+    timer::{TimerHandle, TimerWheel, TIMERS},
 };
 use alloc::sync::Arc;
+use arrayvec::ArrayVec;
+use core::{
+    ops::BitOr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+/// The 8042 PS/2 controller.
+///
+/// [`https://wiki.osdev.org/I8042_PS/2_Controller`]
+pub struct Ps2Controller {
+    registers: Arc<PortRegisters>,
+    /// Whether each port passed its interface test.
+    ports: [bool; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    First,
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// Another driver holds the controller's ports.
+    PortsInUse,
+    /// The controller did not respond in time.
+    Timeout,
+    SelfTestFailed(u8),
+    NoWorkingPorts,
+    /// The port failed its interface test or the controller has no second port.
+    PortUnavailable(Ps2Port),
+    Device(CommandError),
+}
+
+impl From<CommandError> for Ps2Error {
+    fn from(err: CommandError) -> Self {
+        Self::Device(err)
+    }
+}
+
+impl Ps2Controller {
+    const DISABLE_FIRST_PORT: u8 = 0xAD;
+    const ENABLE_FIRST_PORT: u8 = 0xAE;
+    const DISABLE_SECOND_PORT: u8 = 0xA7;
+    const ENABLE_SECOND_PORT: u8 = 0xA8;
+    const READ_CONFIG: u8 = 0x20;
+    const WRITE_CONFIG: u8 = 0x60;
+    const SELF_TEST: u8 = 0xAA;
+    const TEST_FIRST_PORT: u8 = 0xAB;
+    const TEST_SECOND_PORT: u8 = 0xA9;
+
+    const CONFIG_FIRST_IRQ: u8 = 1;
+    const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+    const CONFIG_FIRST_CLOCK_DISABLED: u8 = 1 << 4;
+    const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+    const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+    /// Initializes the controller and tests both of its ports, leaving the working ones enabled
+    /// with their interrupts on.
+    pub fn new(port_manager: &mut PortManager) -> Result<Self, Ps2Error> {
+        let data = unsafe { port_manager.request_port(0x60) };
+        let status_and_command_register = unsafe { port_manager.request_port(0x64) };
+        let (Some(data), Some(status_and_command_register)) = (data, status_and_command_register)
+        else {
+            return Err(Ps2Error::PortsInUse);
+        };
+        let registers = PortRegisters {
+            data,
+            status_and_command_register,
+            dual_channel: false,
+            incoming: [CircularBuffer::new(16), CircularBuffer::new(16)],
+        };
+
+        let (ports, dual_channel) = Self::initialize(&registers)?;
+        Ok(Self {
+            registers: Arc::new(PortRegisters {
+                dual_channel,
+                ..registers
+            }),
+            ports,
+        })
+    }
+
+    /// Returns the ports that passed their interface test, and whether the controller has a
+    /// second port at all.
+    // https://wiki.osdev.org/%228042%22_PS/2_Controller#Initialising_the_PS/2_Controller
+    fn initialize(registers: &impl Registers) -> Result<([bool; 2], bool), Ps2Error> {
+        // Disable devices
+        registers.command(Self::DISABLE_FIRST_PORT)?;
+        registers.command(Self::DISABLE_SECOND_PORT)?;
+
+        // Flush output buf
+        registers.flush();
+
+        // Disable interrupts and translation, and enable the first port's clock
+        let config = registers.command_with_response(Self::READ_CONFIG)?;
+        let new_config = config
+            & !Self::CONFIG_FIRST_IRQ
+            & !Self::CONFIG_SECOND_IRQ
+            & !Self::CONFIG_TRANSLATION
+            & !Self::CONFIG_FIRST_CLOCK_DISABLED;
+        registers.set_config(new_config)?;
+
+        // Self test, which may reset the config
+        let result = registers.command_with_response(Self::SELF_TEST)?;
+        if result != 0x55 {
+            return Err(Ps2Error::SelfTestFailed(result));
+        }
+        registers.set_config(new_config)?;
+
+        // A second port's clock is disabled along with it, so enabling it clears the bit
+        let mut dual_channel = false;
+        if new_config & Self::CONFIG_SECOND_CLOCK_DISABLED != 0 {
+            registers.command(Self::ENABLE_SECOND_PORT)?;
+            let config = registers.command_with_response(Self::READ_CONFIG)?;
+            dual_channel = config & Self::CONFIG_SECOND_CLOCK_DISABLED == 0;
+            if dual_channel {
+                registers.command(Self::DISABLE_SECOND_PORT)?;
+            }
+        }
+
+        // Interface tests
+        let first_port = registers.command_with_response(Self::TEST_FIRST_PORT)? == 0;
+        let second_port =
+            dual_channel && registers.command_with_response(Self::TEST_SECOND_PORT)? == 0;
+        if !first_port && !second_port {
+            return Err(Ps2Error::NoWorkingPorts);
+        }
+
+        // Enable devices and their interrupts
+        let mut config = registers.command_with_response(Self::READ_CONFIG)?;
+        if first_port {
+            registers.command(Self::ENABLE_FIRST_PORT)?;
+            config |= Self::CONFIG_FIRST_IRQ;
+        }
+        if second_port {
+            registers.command(Self::ENABLE_SECOND_PORT)?;
+            config = config & !Self::CONFIG_SECOND_CLOCK_DISABLED | Self::CONFIG_SECOND_IRQ;
+        }
+        registers.set_config(config)?;
+
+        Ok(([first_port, second_port], dual_channel))
+    }
+
+    pub fn has_port(&self, port: Ps2Port) -> bool {
+        self.ports[port as usize]
+    }
+}
+
+/// Status reads take about a microsecond, so this many polls roughly make up `timeout`.
+///
+/// Waits are bounded by both, as [`Instant`] stands still with interrupts disabled while the
+/// PIT is the clock source.
+fn poll_budget(timeout: Duration) -> u32 {
+    u32::try_from(timeout.as_micros()).unwrap_or(u32::MAX)
+}
+
+/// The controller's data register and its status and command register.
+///
+/// Only the raw accesses are implemented, the handshaking on top of them is shared, so that
+/// [`Ps2Controller::initialize`] can be tested against a fake controller.
+trait Registers {
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    const OUTPUT_FULL: u8 = 1;
+    const INPUT_FULL: u8 = 1 << 1;
+    /// Set along with [`Self::OUTPUT_FULL`] for bytes from the second port.
+    const SECOND_PORT_OUTPUT_FULL: u8 = 1 << 5;
+
+    const WRITE_SECOND_PORT: u8 = 0xD4;
+
+    /// Whether the controller has a second port, whose bytes are told apart by
+    /// [`Self::SECOND_PORT_OUTPUT_FULL`].
+    fn dual_channel(&self) -> bool;
+
+    fn status(&self) -> u8;
+
+    fn read_data_register(&self) -> u8;
+
+    fn write_data_register(&self, byte: u8);
+
+    fn write_command_register(&self, command: u8);
+
+    /// Spins until `f` holds for the status register, for at most [`Self::TIMEOUT`].
+    fn wait_for_status(&self, f: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+        let deadline = Instant::now() + Self::TIMEOUT;
+        for _ in 0..poll_budget(Self::TIMEOUT) {
+            if f(self.status()) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn command(&self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for_status(|status| status & Self::INPUT_FULL == 0)?;
+        self.write_command_register(command);
+        Ok(())
+    }
+
+    fn command_with_response(&self, command: u8) -> Result<u8, Ps2Error> {
+        self.command(command)?;
+        self.read_data()
+    }
+
+    fn set_config(&self, config: u8) -> Result<(), Ps2Error> {
+        self.command(Ps2Controller::WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    fn read_data(&self) -> Result<u8, Ps2Error> {
+        self.wait_for_status(|status| status & Self::OUTPUT_FULL != 0)?;
+        Ok(self.read_data_register())
+    }
+
+    /// Writes a byte for the controller or the device on the first port.
+    fn write_data(&self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_for_status(|status| status & Self::INPUT_FULL == 0)?;
+        self.write_data_register(byte);
+        Ok(())
+    }
+
+    fn write_to(&self, port: Ps2Port, byte: u8) -> Result<(), Ps2Error> {
+        if port == Ps2Port::Second {
+            self.command(Self::WRITE_SECOND_PORT)?;
+        }
+        self.write_data(byte)
+    }
+
+    /// The port the byte in the output buffer came from, if there is one.
+    fn output_port(&self) -> Option<Ps2Port> {
+        let status = self.status();
+        if status & Self::OUTPUT_FULL == 0 {
+            None
+        } else if self.dual_channel() && status & Self::SECOND_PORT_OUTPUT_FULL != 0 {
+            Some(Ps2Port::Second)
+        } else {
+            Some(Ps2Port::First)
+        }
+    }
+
+    /// Reads every byte the controller holds into the queue in `incoming` of the port it came
+    /// from.
+    ///
+    /// The output buffer is shared, so a device reading only its own bytes would stall on a
+    /// byte of the other port. Either device drains for both instead.
+    fn drain_into(&self, incoming: &[CircularBuffer<u8>; 2]) {
+        while let Some(port) = self.output_port() {
+            let byte = self.read_data_register();
+            incoming[port as usize].write(byte);
+        }
+    }
+
+    /// Discards bytes left in the output buffer, at most 16 in case a device keeps sending.
+    fn flush(&self) {
+        for _ in 0..16 {
+            if self.status() & Self::OUTPUT_FULL == 0 {
+                break;
+            }
+            let _ = self.read_data_register();
+        }
+    }
+}
+
+/// The controller's registers, shared by the devices on both ports.
+struct PortRegisters {
+    data: Port,
+    status_and_command_register: Port,
+    dual_channel: bool,
+    /// Bytes read from the controller, by the port they came from.
+    incoming: [CircularBuffer<u8>; 2],
+}
+
+impl Registers for PortRegisters {
+    fn dual_channel(&self) -> bool {
+        self.dual_channel
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { self.status_and_command_register.read() }
+    }
+
+    fn read_data_register(&self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    fn write_data_register(&self, byte: u8) {
+        unsafe { self.data.write(byte) }
+    }
+
+    fn write_command_register(&self, command: u8) {
+        unsafe { self.status_and_command_register.write(command) }
+    }
+}
 
 pub struct Ps2Keyboard {
+    device: Arc<SpinLock<Ps2Device>>,
     input: Arc<CircularBuffer<KeyboardInput>>,
+    tasklet: TaskletHandle,
+    /// Encoded [`Typematic`] of the keyboard, shared with the tasklet.
+    typematic: Arc<AtomicU8>,
+    software_repeat: Arc<AtomicBool>,
 }
 
 impl Ps2Keyboard {
+    /// Resets and identifies the keyboard on the controller's first port.
     pub fn new(
-        port_manager: &mut PortManager,
+        controller: &Ps2Controller,
         interrupt_lookup: &InterruptLookup,
         pic: &mut Pic,
-    ) -> Self {
-        let mut data = unsafe {
-            port_manager
-                .request_port(0x60)
-                .expect("only one Ps2Keyboard driver may be active")
-        };
-        let mut status_and_command_register = unsafe {
-            port_manager
-                .request_port(0x64)
-                .expect("only one Ps2Keyboard driver may be active")
-        };
-        unsafe { init_ps2(&mut status_and_command_register, &mut data) };
+    ) -> Result<Self, Ps2Error> {
+        if !controller.has_port(Ps2Port::First) {
+            return Err(Ps2Error::PortUnavailable(Ps2Port::First));
+        }
+
+        let device = Arc::new(SpinLock::new(Ps2Device {
+            registers: controller.registers.clone(),
+            port: Ps2Port::First,
+            received: CircularBuffer::new(16),
+            commands: CommandQueue::default(),
+        }));
+        // The interrupt handler is only registered for a keyboard that responds
+        Self::detect(&device)?;
 
         let input = Arc::new(CircularBuffer::new(8));
-        let hanlder_input = input.clone();
 
-        let mut last_scan_code = 0;
+        // Scan code translation is deferred, the interrupt handler only drains the data port.
+        let tasklet_input = input.clone();
+        let tasklet_device = device.clone();
+        let mut decoder = ScanCodeDecoder::default();
+        let mut translator = KeyTranslator::default();
+        let mut leds = Leds::default();
+        let typematic = Arc::new(AtomicU8::new(Typematic::DEFAULT.0));
+        let software_repeat = Arc::new(AtomicBool::new(false));
+        let tasklet_typematic = typematic.clone();
+        let tasklet_software_repeat = software_repeat.clone();
+        let mut repeater = SoftwareRepeat::new(&TIMERS);
+        let tasklet = DEFERRED_WORK.register(move || loop {
+            let data = interrupt::without_interrupts(|| {
+                let mut device = tasklet_device.lock();
+                device.check_timeout();
+                device.received.read()
+            });
+            let Some(data) = data else {
+                break;
+            };
+            let Some(input) = decoder.feed(data) else {
+                continue;
+            };
+
+            if input.key_code == KeyCode::Unknown {
+                crate::warn!("Unknown scan code: {:#x}", data);
+            }
+            let input = translator.translate(input);
+            if input.modifiers.leds() != leds {
+                leds = input.modifiers.leds();
+                let queued = interrupt::without_interrupts(|| {
+                    tasklet_device.lock().push(Command::SetLeds(leds), false)
+                });
+                if let Err(err) = queued {
+                    crate::warn!("Failed to update keyboard LEDs: {:?}", err);
+                }
+            }
+
+            if tasklet_software_repeat.load(Ordering::Acquire) {
+                if input.repeat {
+                    continue;
+                }
+                let typematic = Typematic(tasklet_typematic.load(Ordering::Acquire));
+                repeater.update(&input, typematic, &tasklet_input);
+            } else {
+                repeater.stop();
+            }
+            tasklet_input.write(input);
+        });
+
         let pic_id = IrqId::Pic1(1);
         pic.unmask(pic_id);
+        let irq_device = device.clone();
+        let irq_tasklet = tasklet.clone();
         interrupt_lookup.register_handler(InterruptHandler::Pic(PicHandler::new(
             pic_id,
             move || {
-                while unsafe { status_and_command_register.read() & 2 > 0 } {}
-                let data = unsafe { data.read() };
-
-                if data == 0xF0 {
-                    last_scan_code = 0xF0;
-                    return;
+                if irq_device.lock().drain() {
+                    irq_tasklet.schedule();
                 }
-
-                let state = if last_scan_code == 0xF0 {
-                    KeyState::Released
-                } else {
-                    KeyState::Pressed
-                };
-
-                let key_code: KeyCode = ScanCode(data).into();
-                last_scan_code = data;
-                hanlder_input.write(KeyboardInput { key_code, state });
             },
         )));
+        // Scan codes may have arrived during detection
+        tasklet.schedule();
+
+        Ok(Self {
+            device,
+            input,
+            tasklet,
+            typematic,
+            software_repeat,
+        })
+    }
+
+    fn detect(device: &SpinLock<Ps2Device>) -> Result<(), Ps2Error> {
+        // Without a keyboard, the reset times out
+        Ps2Device::execute(device, Command::Reset, true)?;
+        Ps2Device::execute(device, Command::DisableScanning, true)?;
+        let id = Ps2Device::execute(device, Command::Identify, true);
+        Ps2Device::execute(device, Command::EnableScanning, true)?;
 
-        Self { input }
+        match id.as_deref() {
+            Ok([0xAB, 0x83]) => {
+                info!("MF2 Keyboard ... [\x1b[32mConnected\x1b[00m]");
+            }
+            _ => {
+                info!("Unknown ... [\x1b[32mConnected\x1b[00m]")
+            }
+        }
+        Ok(())
     }
 
+    /// Input is only produced once the deferred scan code translation has run, see
+    /// [`DEFERRED_WORK`].
     pub fn read_input_with(&self, mut f: impl FnMut(KeyboardInput)) {
         while let Some(input) = self.input.read() {
             f(input);
         }
     }
+
+    /// Queues `command` behind any commands still in flight, without waiting for its response.
+    pub fn send(&self, command: Command) -> Result<CommandHandle, CommandError> {
+        interrupt::without_interrupts(|| self.device.lock().push(command, true))
+            .map(|id| CommandHandle(id.expect("a waited for command has an id")))
+    }
+
+    /// Blocks until the keyboard has responded to `handle`'s command or timed out.
+    ///
+    /// With interrupts disabled, the controller is polled instead of waiting for IRQ1.
+    pub fn wait(&self, handle: CommandHandle) -> Result<Response, CommandError> {
+        let polling = !interrupt::interrupts_enabled();
+        let result = Ps2Device::wait(&self.device, handle.0, polling);
+        if polling {
+            self.tasklet.schedule();
+        }
+        result
+    }
+
+    /// Sends `command` and blocks until the keyboard has responded or timed out, see
+    /// [`Self::wait`].
+    pub fn execute(&self, command: Command) -> Result<Response, CommandError> {
+        let polling = !interrupt::interrupts_enabled();
+        let result = Ps2Device::execute(&self.device, command, polling);
+        if polling {
+            self.tasklet.schedule();
+        }
+        result
+    }
+
+    /// Sets the keyboard LEDs, until the next lock key toggles them.
+    pub fn set_leds(&self, leds: Leds) -> Result<(), CommandError> {
+        self.execute(Command::SetLeds(leds)).map(|_| ())
+    }
+
+    /// Configures the keyboard's own repeats, also the timing of software repeats.
+    pub fn set_typematic(&self, typematic: Typematic) -> Result<(), CommandError> {
+        self.execute(Command::SetTypematic(typematic))?;
+        self.typematic.store(typematic.0, Ordering::Release);
+        Ok(())
+    }
+
+    /// Repeats held keys with timers instead of passing on the keyboard's repeats, so that the
+    /// repeat timing does not depend on the keyboard. Repeats carry the modifiers and text of the
+    /// original press. Requires the [`timer`] wheel.
+    pub fn set_software_repeat(&self, enabled: bool) {
+        self.software_repeat.store(enabled, Ordering::Release);
+    }
+
+    pub fn echo(&self) -> Result<(), CommandError> {
+        self.execute(Command::Echo).map(|_| ())
+    }
+
+    /// The device's identification bytes, empty for an AT keyboard.
+    pub fn identify(&self) -> Result<Response, CommandError> {
+        self.execute(Command::Identify)
+    }
+}
+
+/// A device on one of the controller's ports, shared with its interrupt handler.
+struct Ps2Device {
+    registers: Arc<PortRegisters>,
+    port: Ps2Port,
+    /// Bytes that are not command responses, scan codes or mouse packets.
+    received: CircularBuffer<u8>,
+    commands: CommandQueue,
+}
+
+impl Ps2Device {
+    /// Drains the controller and handles the bytes for this device, routing command responses
+    /// to the queue. Returns whether any other bytes were buffered.
+    ///
+    /// Bytes from the other port are left queued for that port's device.
+    fn drain(&mut self) -> bool {
+        self.registers.drain_into(&self.registers.incoming);
+        let mut received = false;
+        while let Some(byte) = self.registers.incoming[self.port as usize].read() {
+            let (commands, send) = self.commands();
+            if !commands.receive(byte, Instant::now(), send) {
+                self.received.write(byte);
+                received = true;
+            }
+        }
+        received
+    }
+
+    fn push(&mut self, command: Command, wait: bool) -> Result<Option<u32>, CommandError> {
+        let (commands, send) = self.commands();
+        commands.push(command, wait, Instant::now(), send)
+    }
+
+    fn check_timeout(&mut self) {
+        let (commands, send) = self.commands();
+        commands.check_timeout(Instant::now(), send);
+    }
+
+    fn check_timeout_polling(&mut self) {
+        let (commands, send) = self.commands();
+        commands.check_timeout_polling(Instant::now(), send);
+    }
+
+    /// The queue along with a function writing its bytes to the device.
+    fn commands(&mut self) -> (&mut CommandQueue, impl FnMut(u8) + '_) {
+        let registers = &self.registers;
+        let port = self.port;
+        // A byte that is not accepted in time times out the command
+        let send = move |byte| {
+            let _ = registers.write_to(port, byte);
+        };
+        (&mut self.commands, send)
+    }
+
+    /// Blocks until the result of command `id`. With `polling`, the controller is read directly
+    /// instead of relying on the interrupt handler.
+    fn wait(device: &SpinLock<Self>, id: u32, polling: bool) -> Result<Response, CommandError> {
+        loop {
+            let result = interrupt::without_interrupts(|| {
+                let mut device = device.lock();
+                if polling {
+                    device.drain();
+                    device.check_timeout_polling();
+                } else {
+                    device.check_timeout();
+                }
+                device.commands.take_result(id)
+            });
+            if let Some(result) = result {
+                return result;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Queues `command` and blocks until its result, see [`Self::wait`].
+    fn execute(
+        device: &SpinLock<Self>,
+        command: Command,
+        polling: bool,
+    ) -> Result<Response, CommandError> {
+        let id = interrupt::without_interrupts(|| device.lock().push(command, true))?;
+        Self::wait(device, id.expect("a waited for command has an id"), polling)
+    }
 }
 
-#[derive(Debug)]
+/// Keyboard LED state, see [`Command::SetLeds`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    fn bits(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+/// Device command. Keyboards and mice share the command set, but not every command.
+///
+/// [`https://wiki.osdev.org/PS/2_Keyboard#Commands`]
+/// [`https://wiki.osdev.org/PS/2_Mouse#Mouse_Device_Over_PS.2F2`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetLeds(Leds),
+    SetTypematic(Typematic),
+    /// Mouse packets per second.
+    SetSampleRate(u8),
+    SetDefaults,
+    /// Answered with `0xEE` instead of an ACK.
+    Echo,
+    /// Answered with up to two identification bytes.
+    Identify,
+    /// Enables scan codes, or packets for a mouse.
+    EnableScanning,
+    DisableScanning,
+    /// Answered with the result of the device's self test, which a mouse follows with its id.
+    Reset,
+}
+
+impl Command {
+    const ACK: u8 = 0xFA;
+    const RESEND: u8 = 0xFE;
+    /// Sent instead of an ACK for a byte the device cannot handle.
+    const ERROR: u8 = 0xFC;
+    const ECHO: u8 = 0xEE;
+    const SELF_TEST_PASSED: u8 = 0xAA;
+
+    /// Byte `index` of the command, each byte is acknowledged separately.
+    fn byte(&self, index: usize) -> Option<u8> {
+        let bytes: &[u8] = match self {
+            Self::SetLeds(leds) => return [0xED, leds.bits()].get(index).copied(),
+            Self::SetTypematic(typematic) => return [0xF3, typematic.0].get(index).copied(),
+            Self::SetSampleRate(rate) => return [0xF3, *rate].get(index).copied(),
+            Self::SetDefaults => &[0xF6],
+            Self::Echo => &[0xEE],
+            Self::Identify => &[0xF2],
+            Self::EnableScanning => &[0xF4],
+            Self::DisableScanning => &[0xF5],
+            Self::Reset => &[0xFF],
+        };
+        bytes.get(index).copied()
+    }
+
+    /// Number of bytes following the last ACK.
+    fn response_len(&self) -> usize {
+        match self {
+            Self::Identify => 2,
+            Self::Reset => 1,
+            _ => 0,
+        }
+    }
+
+    /// How long to wait for each ACK and for the response.
+    fn timeout(&self) -> Duration {
+        match self {
+            Self::Reset => Duration::from_millis(1000),
+            _ => Duration::from_millis(20),
+        }
+    }
+}
+
+/// Bytes a command responded with, after its ACKs.
+pub type Response = ArrayVec<u8, 2>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    QueueFull,
+    /// The device asked for the command to be resent too often.
+    Resend,
+    Timeout,
+    SelfTestFailed,
+    /// The device answered a byte of the command with `0xFC`.
+    Rejected,
+    /// Not one of [`Ps2Mouse::SAMPLE_RATES`].
+    InvalidSampleRate(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub struct CommandHandle(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QueuedCommand {
+    command: Command,
+    /// Set if the result is waited for.
+    id: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum CommandStage {
+    #[default]
+    Idle,
+    /// Waiting for the ACK of byte `index`.
+    Sending {
+        index: usize,
+    },
+    Response,
+}
+
+/// Sends queued device commands one byte at a time, resending on `0xFE`.
+///
+/// Bytes received while a command is in flight are offered to [`Self::receive`] first, anything
+/// that is not a response is a scan code.
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    queued: ArrayVec<QueuedCommand, 8>,
+    stage: CommandStage,
+    resends: u8,
+    deadline: Option<Instant>,
+    /// Polls left before the command in flight times out, see [`Self::check_timeout_polling`].
+    polls_left: u32,
+    response: Response,
+    results: ArrayVec<(u32, Result<Response, CommandError>), 8>,
+    next_id: u32,
+}
+
+impl CommandQueue {
+    const MAX_RESENDS: u8 = 3;
+
+    /// Queues `command`, sending it right away if the queue is idle. Returns the id to
+    /// [`Self::take_result`] with, if `wait` is set.
+    ///
+    /// A command in flight that has timed out is failed first, so a command nobody waits for
+    /// cannot hold up the queue until the device sends another byte.
+    pub fn push(
+        &mut self,
+        command: Command,
+        wait: bool,
+        now: Instant,
+        mut send: impl FnMut(u8),
+    ) -> Result<Option<u32>, CommandError> {
+        self.check_timeout(now, &mut send);
+
+        let id = wait.then(|| {
+            self.next_id = self.next_id.wrapping_add(1);
+            self.next_id
+        });
+        self.queued
+            .try_push(QueuedCommand { command, id })
+            .map_err(|_| CommandError::QueueFull)?;
+
+        if self.stage == CommandStage::Idle {
+            self.start(now, send);
+        }
+        Ok(id)
+    }
+
+    /// Handles a byte from the device. Returns `false` if it is not a response.
+    pub fn receive(&mut self, byte: u8, now: Instant, mut send: impl FnMut(u8)) -> bool {
+        let Some(queued) = self.queued.first().copied() else {
+            return false;
+        };
+        let command = queued.command;
+
+        match (self.stage, byte) {
+            (CommandStage::Sending { .. }, Command::ECHO) if command == Command::Echo => {
+                self.response.push(byte);
+                self.finish(Ok(()), now, send);
+            }
+            (CommandStage::Sending { index }, Command::ACK) => match command.byte(index + 1) {
+                Some(next) => {
+                    self.stage = CommandStage::Sending { index: index + 1 };
+                    self.resends = 0;
+                    self.arm(now, command.timeout());
+                    send(next);
+                }
+                None if command.response_len() > 0 => {
+                    self.stage = CommandStage::Response;
+                    self.arm(now, command.timeout());
+                }
+                None => self.finish(Ok(()), now, send),
+            },
+            (CommandStage::Sending { .. }, Command::ERROR) => {
+                self.finish(Err(CommandError::Rejected), now, send);
+            }
+            (CommandStage::Sending { index }, Command::RESEND) => {
+                self.resends += 1;
+                if self.resends > Self::MAX_RESENDS {
+                    self.finish(Err(CommandError::Resend), now, send);
+                } else {
+                    self.arm(now, command.timeout());
+                    send(command.byte(index).expect("index is within the command"));
+                }
+            }
+            (CommandStage::Response, byte) => {
+                if command == Command::Reset && byte != Command::SELF_TEST_PASSED {
+                    self.finish(Err(CommandError::SelfTestFailed), now, send);
+                    return true;
+                }
+                self.response.push(byte);
+                if self.response.len() == command.response_len() {
+                    self.finish(Ok(()), now, send);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Fails the command in flight if the device has not answered in time.
+    pub fn check_timeout(&mut self, now: Instant, send: impl FnMut(u8)) {
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            self.time_out(now, send);
+        }
+    }
+
+    /// Like [`Self::check_timeout`], for callers that poll the controller between calls. Each
+    /// call also counts against a budget of polls, as the clock may stand still.
+    pub fn check_timeout_polling(&mut self, now: Instant, send: impl FnMut(u8)) {
+        if self.deadline.is_none() {
+            return;
+        }
+        self.polls_left = self.polls_left.saturating_sub(1);
+        if self.polls_left == 0 {
+            self.time_out(now, send);
+        } else {
+            self.check_timeout(now, send);
+        }
+    }
+
+    fn time_out(&mut self, now: Instant, send: impl FnMut(u8)) {
+        // Identification is variable length, an AT keyboard sends no bytes at all
+        let result = match (self.stage, self.queued[0].command) {
+            (CommandStage::Response, Command::Identify) => Ok(()),
+            _ => Err(CommandError::Timeout),
+        };
+        self.finish(result, now, send);
+    }
+
+    pub fn take_result(&mut self, id: u32) -> Option<Result<Response, CommandError>> {
+        let index = self
+            .results
+            .iter()
+            .position(|(result_id, _)| *result_id == id)?;
+        Some(self.results.remove(index).1)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stage == CommandStage::Idle
+    }
+
+    fn finish(&mut self, result: Result<(), CommandError>, now: Instant, send: impl FnMut(u8)) {
+        let finished = self.queued.remove(0);
+        let response = core::mem::take(&mut self.response);
+        if let Some(id) = finished.id {
+            // Results nobody waits for anymore make room
+            if self.results.is_full() {
+                let _ = self.results.remove(0);
+            }
+            self.results.push((id, result.map(|_| response)));
+        }
+
+        self.stage = CommandStage::Idle;
+        self.deadline = None;
+        self.start(now, send);
+    }
+
+    fn arm(&mut self, now: Instant, timeout: Duration) {
+        self.deadline = Some(now + timeout);
+        self.polls_left = poll_budget(timeout);
+    }
+
+    fn start(&mut self, now: Instant, mut send: impl FnMut(u8)) {
+        let Some(queued) = self.queued.first().copied() else {
+            return;
+        };
+        self.stage = CommandStage::Sending { index: 0 };
+        self.resends = 0;
+        self.arm(now, queued.command.timeout());
+        send(
+            queued
+                .command
+                .byte(0)
+                .expect("commands are at least one byte"),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct KeyboardInput {
     pub key_code: KeyCode,
     pub state: KeyState,
+    /// Modifier state including this event.
+    pub modifiers: Modifiers,
+    /// The text this key press produces, if any.
+    pub character: Option<char>,
+    /// Set on presses of a key that is already held, from the keyboard's typematic repeat or
+    /// from [`Ps2Keyboard::set_software_repeat`].
+    pub repeat: bool,
+}
+
+impl KeyboardInput {
+    /// A raw key event, without modifiers or text.
+    pub fn new(key_code: KeyCode, state: KeyState) -> Self {
+        Self {
+            key_code,
+            state,
+            modifiers: Modifiers::default(),
+            character: None,
+            repeat: false,
+        }
+    }
+}
+
+/// Held modifier keys and the state of the lock keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift_left: bool,
+    pub shift_right: bool,
+    pub control_left: bool,
+    pub control_right: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub meta_left: bool,
+    pub meta_right: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.shift_left || self.shift_right
+    }
+
+    pub fn control(&self) -> bool {
+        self.control_left || self.control_right
+    }
+
+    pub fn meta(&self) -> bool {
+        self.meta_left || self.meta_right
+    }
+
+    pub fn leds(&self) -> Leds {
+        Leds {
+            scroll_lock: self.scroll_lock,
+            num_lock: self.num_lock,
+            caps_lock: self.caps_lock,
+        }
+    }
+
+    /// Applies a key event. Returns whether the key is a modifier or lock key.
+    pub fn update(&mut self, input: &KeyboardInput) -> bool {
+        let pressed = input.state == KeyState::Pressed;
+        let held = match input.key_code {
+            KeyCode::ShiftLeft => &mut self.shift_left,
+            KeyCode::ShiftRight => &mut self.shift_right,
+            KeyCode::ControlLeft => &mut self.control_left,
+            KeyCode::ControlRight => &mut self.control_right,
+            KeyCode::AltLeft => &mut self.alt,
+            KeyCode::AltRight => &mut self.alt_gr,
+            KeyCode::MetaLeft => &mut self.meta_left,
+            KeyCode::MetaRight => &mut self.meta_right,
+            KeyCode::CapsLock | KeyCode::NumLock | KeyCode::ScrollLock => {
+                // Lock keys toggle on every press passed in, `KeyTranslator::translate` leaves out
+                // typematic repeats
+                if pressed {
+                    let lock = match input.key_code {
+                        KeyCode::CapsLock => &mut self.caps_lock,
+                        KeyCode::NumLock => &mut self.num_lock,
+                        _ => &mut self.scroll_lock,
+                    };
+                    *lock = !*lock;
+                }
+                return true;
+            }
+            _ => return false,
+        };
+        *held = pressed;
+        true
+    }
+}
+
+/// Tracks modifiers across key events and translates key presses into text, using the active
+/// [`keymap`].
+#[derive(Debug, Default)]
+pub struct KeyTranslator {
+    modifiers: Modifiers,
+    /// Bit per [`KeyCode`], to tell typematic repeats from the first press.
+    held: u128,
+}
+
+const _: () = assert!((KeyCode::Unknown as usize) < u128::BITS as usize);
+
+impl KeyTranslator {
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Fills in the modifiers, the text and the repeat flag of a raw key event.
+    pub fn translate(&mut self, mut input: KeyboardInput) -> KeyboardInput {
+        let bit = 1 << input.key_code as u32;
+        let pressed = input.state == KeyState::Pressed;
+        input.repeat = pressed && self.held & bit != 0;
+        if pressed {
+            self.held |= bit;
+        } else {
+            self.held &= !bit;
+        }
+
+        // Lock keys only toggle on their first press
+        if !input.repeat {
+            self.modifiers.update(&input);
+        }
+        input.modifiers = self.modifiers;
+
+        if pressed {
+            input.character = keymap::active().character(input.key_code, &self.modifiers);
+        }
+        input
+    }
+}
+
+/// Delay before a held key repeats, and the period it repeats at.
+///
+/// [`https://wiki.osdev.org/PS/2_Keyboard#Commands`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic(u8);
+
+impl Typematic {
+    /// 500 ms delay, 10.9 repeats per second.
+    pub const DEFAULT: Self = Self(0x2B);
+
+    /// The closest supported setting, delays are 250 ms to 1 s in steps of 250 ms and periods
+    /// 33 ms to 500 ms.
+    pub fn new(delay: Duration, period: Duration) -> Self {
+        let delay = ((delay.as_millis() + 125) / 250).clamp(1, 4) as u8 - 1;
+        let period_us = period.as_micros();
+        let rate = (0..32)
+            .min_by_key(|rate| Self::period_us(*rate).abs_diff(period_us as u64))
+            .expect("rates are not empty");
+        Self(delay << 5 | rate)
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(250 * ((self.0 >> 5) as u64 + 1))
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_micros(Self::period_us(self.0 & 0x1F))
+    }
+
+    /// `(8 + A) * 2^B * 4.17 ms`, with `A` the lower three bits of the rate and `B` the upper two.
+    fn period_us(rate: u8) -> u64 {
+        (8 + (rate & 0b111) as u64) * (1 << (rate >> 3)) * 4167
+    }
+}
+
+impl Default for Typematic {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Repeats the last pressed key with timers, in place of the keyboard's own repeats.
+struct SoftwareRepeat {
+    timers: &'static TimerWheel,
+    /// The repeating key and the timer of its next repeat.
+    repeating: Option<(KeyCode, Arc<SpinLock<Option<TimerHandle>>>)>,
+}
+
+impl SoftwareRepeat {
+    fn new(timers: &'static TimerWheel) -> Self {
+        Self {
+            timers,
+            repeating: None,
+        }
+    }
+
+    fn update(
+        &mut self,
+        input: &KeyboardInput,
+        typematic: Typematic,
+        output: &Arc<CircularBuffer<KeyboardInput>>,
+    ) {
+        match input.state {
+            KeyState::Pressed if !input.key_code.is_modifier() => {
+                self.stop();
+
+                let repeat = KeyboardInput {
+                    repeat: true,
+                    ..*input
+                };
+                let output = output.clone();
+                let timer = Arc::new(SpinLock::new(None));
+                // Replaced by the periodic timer once the delay has passed
+                let delay_timer = timer.clone();
+                let timers = self.timers;
+                let delay = timers.after(typematic.delay(), move || {
+                    output.write(repeat);
+                    let output = output.clone();
+                    let periodic = timers.every(typematic.period(), move || output.write(repeat));
+                    *delay_timer.lock() = periodic.ok();
+                });
+                // Without the PIT there are no timers, and keys do not repeat
+                if let Ok(delay) = delay {
+                    *timer.lock() = Some(delay);
+                    self.repeating = Some((input.key_code, timer));
+                }
+            }
+            KeyState::Released
+                if self
+                    .repeating
+                    .as_ref()
+                    .is_some_and(|(key_code, _)| *key_code == input.key_code) =>
+            {
+                self.stop();
+            }
+            _ => {}
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(timer) = self
+            .repeating
+            .take()
+            .and_then(|(_, timer)| timer.lock().take())
+        {
+            timer.cancel();
+        }
+    }
+}
+
+/// Physical key, named after its position on a US layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum KeyCode {
     KeyA,
-
-    KeyW,
-    KeyS,
+    KeyB,
+    KeyC,
     KeyD,
+    KeyE,
+    KeyF,
+    KeyG,
+    KeyH,
+    KeyI,
+    KeyJ,
+    KeyK,
+    KeyL,
+    KeyM,
+    KeyN,
+    KeyO,
+    KeyP,
+    KeyQ,
+    KeyR,
+    KeyS,
+    KeyT,
+    KeyU,
+    KeyV,
+    KeyW,
+    KeyX,
+    KeyY,
+    KeyZ,
+
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+
+    Backquote,
+    Minus,
+    Equal,
+    BracketLeft,
+    BracketRight,
+    Backslash,
+    Semicolon,
+    Quote,
+    Comma,
+    Period,
+    Slash,
+    /// The extra key next to left shift on ISO keyboards.
+    IntlBackslash,
+
+    Escape,
+    Tab,
+    CapsLock,
+    Space,
+    Enter,
+    Backspace,
+
+    ShiftLeft,
+    ShiftRight,
+    ControlLeft,
+    ControlRight,
+    AltLeft,
+    /// AltGr on most non-US layouts.
+    AltRight,
+    MetaLeft,
+    MetaRight,
+    ContextMenu,
+
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    PrintScreen,
+    Pause,
+
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+
+    NumLock,
+    ScrollLock,
+
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    NumpadDecimal,
+    NumpadAdd,
+    NumpadSubtract,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadEnter,
 
     Unknown,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl KeyCode {
+    /// Modifier and lock keys, which never produce text nor repeat in software.
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            Self::ShiftLeft
+                | Self::ShiftRight
+                | Self::ControlLeft
+                | Self::ControlRight
+                | Self::AltLeft
+                | Self::AltRight
+                | Self::MetaLeft
+                | Self::MetaRight
+                | Self::CapsLock
+                | Self::NumLock
+                | Self::ScrollLock
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyState {
     Pressed,
     Released,
@@ -94,112 +1275,1065 @@ pub enum KeyState {
 #[derive(Debug)]
 struct ScanCode(u8);
 
-// https://wiki.osdev.org/PS/2_Keyboard
+/// A scan code following the `0xE0` prefix.
+#[derive(Debug)]
+struct ExtendedScanCode(u8);
+
+/// Turns the byte stream of a set 2 keyboard into key events.
+///
+/// Keys send a make code when pressed, and the same code prefixed with `0xF0` when released.
+/// Extended keys are additionally prefixed with `0xE0`. Print Screen is sent as two extended
+/// keys, a fake left shift followed by the actual key, and Pause as an `0xE1` sequence without
+/// any break code:
+///
+/// | Key          | Make                      | Break               |
+/// |--------------|---------------------------|---------------------|
+/// | A            | `1C`                      | `F0 1C`             |
+/// | Right Ctrl   | `E0 14`                   | `E0 F0 14`          |
+/// | Print Screen | `E0 12 E0 7C`             | `E0 F0 7C E0 F0 12` |
+/// | Pause        | `E1 14 77 E1 F0 14 F0 77` | none                |
+///
+/// [`https://wiki.osdev.org/PS/2_Keyboard#Scan_Code_Set_2`]
+#[derive(Debug, Default)]
+pub struct ScanCodeDecoder {
+    state: DecoderState,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    #[default]
+    Start,
+    Release,
+    Extended,
+    ExtendedRelease,
+    /// Within an `0xE1` sequence, counting the bytes received after the prefix.
+    Pause {
+        received: u8,
+    },
+}
+
+impl ScanCodeDecoder {
+    /// Feeds the next byte from the keyboard, returning the event it completes.
+    pub fn feed(&mut self, byte: u8) -> Option<KeyboardInput> {
+        let (state, input) = match (self.state, byte) {
+            // Command responses and errors, never part of a scan code
+            (DecoderState::Start, 0x00 | 0xAA | 0xEE | 0xFA | 0xFC | 0xFD | 0xFE | 0xFF) => {
+                (DecoderState::Start, None)
+            }
+
+            (DecoderState::Start, 0xE0) => (DecoderState::Extended, None),
+            (DecoderState::Start, 0xE1) => (DecoderState::Pause { received: 0 }, None),
+            (DecoderState::Start, 0xF0) => (DecoderState::Release, None),
+            (DecoderState::Start, code) => (
+                DecoderState::Start,
+                Some(KeyboardInput::new(ScanCode(code).into(), KeyState::Pressed)),
+            ),
+            (DecoderState::Release, code) => (
+                DecoderState::Start,
+                Some(KeyboardInput::new(
+                    ScanCode(code).into(),
+                    KeyState::Released,
+                )),
+            ),
+
+            (DecoderState::Extended, 0xF0) => (DecoderState::ExtendedRelease, None),
+            (DecoderState::Extended, code) => (
+                DecoderState::Start,
+                ExtendedScanCode(code)
+                    .key_code()
+                    .map(|key_code| KeyboardInput::new(key_code, KeyState::Pressed)),
+            ),
+            (DecoderState::ExtendedRelease, code) => (
+                DecoderState::Start,
+                ExtendedScanCode(code)
+                    .key_code()
+                    .map(|key_code| KeyboardInput::new(key_code, KeyState::Released)),
+            ),
+
+            // Pressing sends `E1 14 77` and releasing `E1 F0 14 F0 77`, both immediately
+            (DecoderState::Pause { received }, byte) => {
+                let received = received + 1;
+                match (received, byte) {
+                    (2, 0x77) => (
+                        DecoderState::Start,
+                        Some(KeyboardInput::new(KeyCode::Pause, KeyState::Pressed)),
+                    ),
+                    (4, 0x77) => (
+                        DecoderState::Start,
+                        Some(KeyboardInput::new(KeyCode::Pause, KeyState::Released)),
+                    ),
+                    (1, 0x14 | 0xF0) | (2, 0x14) | (3, 0xF0) => {
+                        (DecoderState::Pause { received }, None)
+                    }
+                    _ => (DecoderState::Start, None),
+                }
+            }
+        };
+
+        self.state = state;
+        input
+    }
+}
+
+// https://wiki.osdev.org/PS/2_Keyboard#Scan_Code_Set_2
 impl From<ScanCode> for KeyCode {
     fn from(value: ScanCode) -> Self {
         match value.0 {
+            0x01 => KeyCode::F9,
+            0x03 => KeyCode::F5,
+            0x04 => KeyCode::F3,
+            0x05 => KeyCode::F1,
+            0x06 => KeyCode::F2,
+            0x07 => KeyCode::F12,
+            0x09 => KeyCode::F10,
+            0x0A => KeyCode::F8,
+            0x0B => KeyCode::F6,
+            0x0C => KeyCode::F4,
+            0x0D => KeyCode::Tab,
+            0x0E => KeyCode::Backquote,
+            0x11 => KeyCode::AltLeft,
+            0x12 => KeyCode::ShiftLeft,
+            0x14 => KeyCode::ControlLeft,
+            0x15 => KeyCode::KeyQ,
+            0x16 => KeyCode::Digit1,
+            0x1A => KeyCode::KeyZ,
+            0x1B => KeyCode::KeyS,
             0x1C => KeyCode::KeyA,
-
             0x1D => KeyCode::KeyW,
-            0x1B => KeyCode::KeyS,
+            0x1E => KeyCode::Digit2,
+            0x21 => KeyCode::KeyC,
+            0x22 => KeyCode::KeyX,
             0x23 => KeyCode::KeyD,
+            0x24 => KeyCode::KeyE,
+            0x25 => KeyCode::Digit4,
+            0x26 => KeyCode::Digit3,
+            0x29 => KeyCode::Space,
+            0x2A => KeyCode::KeyV,
+            0x2B => KeyCode::KeyF,
+            0x2C => KeyCode::KeyT,
+            0x2D => KeyCode::KeyR,
+            0x2E => KeyCode::Digit5,
+            0x31 => KeyCode::KeyN,
+            0x32 => KeyCode::KeyB,
+            0x33 => KeyCode::KeyH,
+            0x34 => KeyCode::KeyG,
+            0x35 => KeyCode::KeyY,
+            0x36 => KeyCode::Digit6,
+            0x3A => KeyCode::KeyM,
+            0x3B => KeyCode::KeyJ,
+            0x3C => KeyCode::KeyU,
+            0x3D => KeyCode::Digit7,
+            0x3E => KeyCode::Digit8,
+            0x41 => KeyCode::Comma,
+            0x42 => KeyCode::KeyK,
+            0x43 => KeyCode::KeyI,
+            0x44 => KeyCode::KeyO,
+            0x45 => KeyCode::Digit0,
+            0x46 => KeyCode::Digit9,
+            0x49 => KeyCode::Period,
+            0x4A => KeyCode::Slash,
+            0x4B => KeyCode::KeyL,
+            0x4C => KeyCode::Semicolon,
+            0x4D => KeyCode::KeyP,
+            0x4E => KeyCode::Minus,
+            0x52 => KeyCode::Quote,
+            0x54 => KeyCode::BracketLeft,
+            0x55 => KeyCode::Equal,
+            0x58 => KeyCode::CapsLock,
+            0x59 => KeyCode::ShiftRight,
+            0x5A => KeyCode::Enter,
+            0x5B => KeyCode::BracketRight,
+            0x5D => KeyCode::Backslash,
+            0x61 => KeyCode::IntlBackslash,
+            0x66 => KeyCode::Backspace,
+            0x69 => KeyCode::Numpad1,
+            0x6B => KeyCode::Numpad4,
+            0x6C => KeyCode::Numpad7,
+            0x70 => KeyCode::Numpad0,
+            0x71 => KeyCode::NumpadDecimal,
+            0x72 => KeyCode::Numpad2,
+            0x73 => KeyCode::Numpad5,
+            0x74 => KeyCode::Numpad6,
+            0x75 => KeyCode::Numpad8,
+            0x76 => KeyCode::Escape,
+            0x77 => KeyCode::NumLock,
+            0x78 => KeyCode::F11,
+            0x79 => KeyCode::NumpadAdd,
+            0x7A => KeyCode::Numpad3,
+            0x7B => KeyCode::NumpadSubtract,
+            0x7C => KeyCode::NumpadMultiply,
+            0x7D => KeyCode::Numpad9,
+            0x7E => KeyCode::ScrollLock,
+            0x83 => KeyCode::F7,
 
-            v => {
-                crate::warn!("Unknown scane code: {:?}", v);
-                KeyCode::Unknown
+            _ => KeyCode::Unknown,
+        }
+    }
+}
+
+impl ExtendedScanCode {
+    /// `None` for the fake shifts surrounding Print Screen, which are not keys of their own.
+    fn key_code(&self) -> Option<KeyCode> {
+        let key_code = match self.0 {
+            0x12 | 0x59 => return None,
+
+            0x11 => KeyCode::AltRight,
+            0x14 => KeyCode::ControlRight,
+            0x1F => KeyCode::MetaLeft,
+            0x27 => KeyCode::MetaRight,
+            0x2F => KeyCode::ContextMenu,
+            0x4A => KeyCode::NumpadDivide,
+            0x5A => KeyCode::NumpadEnter,
+            0x69 => KeyCode::End,
+            0x6B => KeyCode::ArrowLeft,
+            0x6C => KeyCode::Home,
+            0x70 => KeyCode::Insert,
+            0x71 => KeyCode::Delete,
+            0x72 => KeyCode::ArrowDown,
+            0x74 => KeyCode::ArrowRight,
+            0x75 => KeyCode::ArrowUp,
+            0x7A => KeyCode::PageDown,
+            0x7C => KeyCode::PrintScreen,
+            0x7D => KeyCode::PageUp,
+
+            _ => KeyCode::Unknown,
+        };
+        Some(key_code)
+    }
+}
+
+/// Mouse on the controller's second port.
+///
+/// [`https://wiki.osdev.org/PS/2_Mouse`]
+pub struct Ps2Mouse {
+    device: Arc<SpinLock<Ps2Device>>,
+    input: Arc<CircularBuffer<MouseInput>>,
+    tasklet: TaskletHandle,
+    kind: MouseKind,
+}
+
+impl Ps2Mouse {
+    const DEFAULT_SAMPLE_RATE: u8 = 100;
+    /// Packets per second a mouse accepts.
+    pub const SAMPLE_RATES: [u8; 7] = [10, 20, 40, 60, 80, 100, 200];
+
+    /// Resets the mouse on the controller's second port and enables its extensions.
+    pub fn new(
+        controller: &Ps2Controller,
+        interrupt_lookup: &InterruptLookup,
+        pic: &mut Pic,
+    ) -> Result<Self, Ps2Error> {
+        if !controller.has_port(Ps2Port::Second) {
+            return Err(Ps2Error::PortUnavailable(Ps2Port::Second));
+        }
+
+        let device = Arc::new(SpinLock::new(Ps2Device {
+            registers: controller.registers.clone(),
+            port: Ps2Port::Second,
+            received: CircularBuffer::new(64),
+            commands: CommandQueue::default(),
+        }));
+        let kind = Self::detect(&device)?;
+        info!("{:?} mouse ... [\x1b[32mConnected\x1b[00m]", kind);
+
+        let input = Arc::new(CircularBuffer::new(16));
+
+        let tasklet_input = input.clone();
+        let tasklet_device = device.clone();
+        let mut decoder = MousePacketDecoder::new(kind);
+        let tasklet = DEFERRED_WORK.register(move || {
+            while let Some(data) = interrupt::without_interrupts(|| {
+                let mut device = tasklet_device.lock();
+                device.check_timeout();
+                device.received.read()
+            }) {
+                if let Some(input) = decoder.feed(data) {
+                    tasklet_input.write(input);
+                }
+            }
+        });
+
+        let pic_id = IrqId::Pic2(4);
+        pic.unmask(pic_id);
+        let irq_device = device.clone();
+        let irq_tasklet = tasklet.clone();
+        interrupt_lookup.register_handler(InterruptHandler::Pic(PicHandler::new(
+            pic_id,
+            move || {
+                if irq_device.lock().drain() {
+                    irq_tasklet.schedule();
+                }
+            },
+        )));
+
+        Ok(Self {
+            device,
+            input,
+            tasklet,
+            kind,
+        })
+    }
+
+    fn detect(device: &SpinLock<Ps2Device>) -> Result<MouseKind, Ps2Error> {
+        Ps2Device::execute(device, Command::Reset, true)?;
+        Ps2Device::execute(device, Command::DisableScanning, true)?;
+
+        // Each extension is unlocked by a magic sequence of sample rates
+        let mut kind = MouseKind::Standard;
+        for (extension, rates) in [
+            (MouseKind::Wheel, [200, 100, 80]),
+            (MouseKind::FiveButton, [200, 200, 80]),
+        ] {
+            for rate in rates {
+                Ps2Device::execute(device, Command::SetSampleRate(rate), true)?;
             }
+            let id = Ps2Device::execute(device, Command::Identify, true)?;
+            if id.first() != Some(&(extension as u8)) {
+                break;
+            }
+            kind = extension;
         }
+
+        Ps2Device::execute(
+            device,
+            Command::SetSampleRate(Self::DEFAULT_SAMPLE_RATE),
+            true,
+        )?;
+        Ps2Device::execute(device, Command::EnableScanning, true)?;
+
+        // The id following the reset is not part of a packet
+        interrupt::without_interrupts(|| while device.lock().received.read().is_some() {});
+        Ok(kind)
+    }
+
+    pub fn kind(&self) -> MouseKind {
+        self.kind
+    }
+
+    /// Input is only produced once the deferred packet decoding has run, see [`DEFERRED_WORK`].
+    pub fn read_input_with(&self, mut f: impl FnMut(MouseInput)) {
+        while let Some(input) = self.input.read() {
+            f(input);
+        }
+    }
+
+    /// Sets the packets per second, one of [`Self::SAMPLE_RATES`].
+    pub fn set_sample_rate(&self, rate: u8) -> Result<(), CommandError> {
+        Self::check_sample_rate(rate)?;
+        self.execute(Command::SetSampleRate(rate)).map(|_| ())
+    }
+
+    fn check_sample_rate(rate: u8) -> Result<(), CommandError> {
+        if !Self::SAMPLE_RATES.contains(&rate) {
+            return Err(CommandError::InvalidSampleRate(rate));
+        }
+        Ok(())
+    }
+
+    /// Sends `command` and blocks until the mouse has responded or timed out. With interrupts
+    /// disabled, the controller is polled instead of waiting for IRQ12.
+    pub fn execute(&self, command: Command) -> Result<Response, CommandError> {
+        let polling = !interrupt::interrupts_enabled();
+        let result = Ps2Device::execute(&self.device, command, polling);
+        if polling {
+            self.tasklet.schedule();
+        }
+        result
     }
 }
 
-// https://wiki.osdev.org/%228042%22_PS/2_Controller#Initialising_the_PS/2_Controller
-unsafe fn init_ps2(status_and_command_register: &mut Port, data: &mut Port) {
-    // Disable devices
-    status_and_command_register.write(0xAD);
-    status_and_command_register.write(0xA7);
+/// Mouse protocol, named after the device id it identifies with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    Standard = 0,
+    /// IntelliMouse with a scroll wheel.
+    Wheel = 3,
+    /// IntelliMouse with a scroll wheel and buttons 4 and 5.
+    FiveButton = 4,
+}
 
-    // Flush output buf
-    let _ = data.read();
+impl MouseKind {
+    fn packet_len(&self) -> usize {
+        match self {
+            Self::Standard => 3,
+            Self::Wheel | Self::FiveButton => 4,
+        }
+    }
+}
 
-    // Enable the first ps2 port
-    status_and_command_register.write(0x20);
-    let config = data.read();
-    let new_config = config & !1 & !(1 << 6) & !(1 << 4);
-    status_and_command_register.write(0x60);
-    data.write(new_config);
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseButtons(u8);
 
-    // Confirm the new config
-    status_and_command_register.write(0x20);
-    let confirm_config = data.read();
-    assert_eq!(new_config, confirm_config);
+impl MouseButtons {
+    pub const LEFT: Self = Self(1);
+    pub const RIGHT: Self = Self(1 << 1);
+    pub const MIDDLE: Self = Self(1 << 2);
+    /// Button 4.
+    pub const BACK: Self = Self(1 << 3);
+    /// Button 5.
+    pub const FORWARD: Self = Self(1 << 4);
 
-    // Self test
-    status_and_command_register.write(0xAA);
-    let result = data.read();
-    assert_eq!(0x55, result);
+    pub fn contains(&self, buttons: MouseButtons) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
 
-    // Restore config
-    status_and_command_register.write(0x60);
-    data.write(new_config);
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
 
-    // Confirm the config
-    status_and_command_register.write(0x20);
-    let confirm_config = data.read();
-    assert_eq!(new_config, confirm_config);
+impl BitOr for MouseButtons {
+    type Output = Self;
 
-    // Skipping step 7 (Determine If There Are 2 Channels)
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
 
-    // More tests
-    status_and_command_register.write(0xAB);
-    let result = data.read();
-    assert_eq!(0, result);
+/// One mouse packet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseInput {
+    /// Relative motion, positive to the right.
+    pub dx: i16,
+    /// Relative motion, positive downwards like screen coordinates.
+    pub dy: i16,
+    /// Wheel clicks, positive when scrolled towards the user.
+    pub wheel: i8,
+    /// Buttons held down.
+    pub buttons: MouseButtons,
+    /// Buttons pressed or released with this packet.
+    pub changed: MouseButtons,
+}
 
-    // Enable devices
-    status_and_command_register.write(0xAE);
+impl MouseInput {
+    pub fn pressed(&self, button: MouseButtons) -> bool {
+        self.changed.contains(button) && self.buttons.contains(button)
+    }
 
-    // Enable interrupts
-    status_and_command_register.write(0x20);
-    let config = data.read();
-    let new_config = config | 1;
-    status_and_command_register.write(0x60);
-    data.write(new_config);
+    pub fn released(&self, button: MouseButtons) -> bool {
+        self.changed.contains(button) && !self.buttons.contains(button)
+    }
+}
 
-    // Reset devices
-    data.write(0xFF);
+/// Assembles mouse packets from single bytes.
+#[derive(Debug)]
+pub struct MousePacketDecoder {
+    kind: MouseKind,
+    packet: ArrayVec<u8, 4>,
+    buttons: MouseButtons,
+}
 
-    // Detecting device in port 1
+impl MousePacketDecoder {
+    const ALWAYS_ONE: u8 = 1 << 3;
+    const X_SIGN: u8 = 1 << 4;
+    const Y_SIGN: u8 = 1 << 5;
+    const X_OVERFLOW: u8 = 1 << 6;
+    const Y_OVERFLOW: u8 = 1 << 7;
 
-    // Disable scanning
-    data.write(0xF5);
-    while status_and_command_register.read() & 2 > 0 {}
-    let result = data.read();
-    assert_eq!(0xFA, result);
+    pub fn new(kind: MouseKind) -> Self {
+        Self {
+            kind,
+            packet: ArrayVec::new(),
+            buttons: MouseButtons::default(),
+        }
+    }
+
+    /// Feeds the next byte from the mouse, returning the input of the packet it completes.
+    pub fn feed(&mut self, byte: u8) -> Option<MouseInput> {
+        // Bytes are dropped until one can start a packet, in case the stream got out of sync
+        if self.packet.is_empty() && byte & Self::ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet.push(byte);
+        if self.packet.len() < self.kind.packet_len() {
+            return None;
+        }
+
+        let packet = core::mem::take(&mut self.packet);
+        let flags = packet[0];
+        // Nine bit two's complement, an overflowed axis is unreliable
+        let axis = |value: u8, sign: u8, overflow: u8| -> i16 {
+            match (flags & overflow != 0, flags & sign != 0) {
+                (true, _) => 0,
+                (false, true) => value as i16 - 0x100,
+                (false, false) => value as i16,
+            }
+        };
+
+        let mut buttons = MouseButtons(flags & 0b111);
+        let wheel = match self.kind {
+            MouseKind::Standard => 0,
+            MouseKind::Wheel => packet[3] as i8,
+            MouseKind::FiveButton => {
+                buttons = buttons | MouseButtons((packet[3] >> 4 & 0b11) << 3);
+                // Four bit two's complement
+                (packet[3] << 4) as i8 >> 4
+            }
+        };
+        let changed = MouseButtons(buttons.0 ^ self.buttons.0);
+        self.buttons = buttons;
+
+        Some(MouseInput {
+            dx: axis(packet[1], Self::X_SIGN, Self::X_OVERFLOW),
+            // The mouse reports upwards motion as positive
+            dy: -axis(packet[2], Self::Y_SIGN, Self::Y_OVERFLOW),
+            wheel,
+            buttons,
+            changed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_case, timer::WheelTime};
+    use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+    use core::{
+        cell::{Cell, RefCell},
+        sync::atomic::AtomicU64,
+    };
+
+    /// Just enough of an 8042 for [`Ps2Controller::initialize`].
+    struct FakeController {
+        dual_channel: bool,
+        config: Cell<u8>,
+        self_test: u8,
+        /// Never accepts a byte.
+        busy: bool,
+        /// Bytes in the output buffer, along with the port they came from.
+        output: RefCell<VecDeque<(Ps2Port, u8)>>,
+        writing_config: Cell<bool>,
+    }
 
-    // Identity command
-    data.write(0xF2);
-    while status_and_command_register.read() & 2 > 0 {}
-    let result = data.read();
-    assert_eq!(0xFA, result);
+    impl FakeController {
+        fn new(dual_channel: bool) -> Self {
+            Self {
+                dual_channel,
+                // Both ports and their interrupts enabled, with translation
+                config: Cell::new(0x43),
+                self_test: 0x55,
+                busy: false,
+                output: RefCell::new(VecDeque::new()),
+                writing_config: Cell::new(false),
+            }
+        }
 
-    while status_and_command_register.read() & 2 > 0 {}
-    let first_id_byte = data.read();
+        fn set_config_bits(&self, bits: u8, set: bool) {
+            let config = self.config.get();
+            self.config
+                .set(if set { config | bits } else { config & !bits });
+        }
+    }
 
-    // TODO: timeout for second identity byte
-    while status_and_command_register.read() & 2 > 0 {}
-    let second_id_byte = data.read();
+    impl Registers for FakeController {
+        fn dual_channel(&self) -> bool {
+            self.dual_channel
+        }
 
-    // Re-enable scanning
-    data.write(0xF4);
-    while status_and_command_register.read() & 2 > 0 {}
-    let result = data.read();
-    assert_eq!(0xFA, result);
+        fn status(&self) -> u8 {
+            let output = match self.output.borrow().front() {
+                Some((Ps2Port::First, _)) => Self::OUTPUT_FULL,
+                Some((Ps2Port::Second, _)) => Self::OUTPUT_FULL | Self::SECOND_PORT_OUTPUT_FULL,
+                None => 0,
+            };
+            output | (self.busy as u8) << 1
+        }
 
-    match (first_id_byte, second_id_byte) {
-        (0xAB, 0x83) => {
-            info!("MF2 Keyboard ... [\x1b[32mConnected\x1b[00m]");
+        fn read_data_register(&self) -> u8 {
+            self.output
+                .borrow_mut()
+                .pop_front()
+                .map_or(0, |(_, byte)| byte)
         }
-        _ => {
-            info!("Unknown ... [\x1b[32mConnected\x1b[00m]")
+
+        fn write_data_register(&self, byte: u8) {
+            if self.writing_config.replace(false) {
+                self.config.set(byte);
+            }
+        }
+
+        fn write_command_register(&self, command: u8) {
+            let mut output = self.output.borrow_mut();
+            let mut output = |byte| output.push_back((Ps2Port::First, byte));
+            match command {
+                Ps2Controller::READ_CONFIG => output(self.config.get()),
+                Ps2Controller::WRITE_CONFIG => self.writing_config.set(true),
+                Ps2Controller::SELF_TEST => output(self.self_test),
+                Ps2Controller::TEST_FIRST_PORT => output(0),
+                Ps2Controller::TEST_SECOND_PORT if self.dual_channel => output(0),
+                Ps2Controller::DISABLE_FIRST_PORT => self.set_config_bits(1 << 4, true),
+                Ps2Controller::ENABLE_FIRST_PORT => self.set_config_bits(1 << 4, false),
+                Ps2Controller::DISABLE_SECOND_PORT if self.dual_channel => {
+                    self.set_config_bits(1 << 5, true)
+                }
+                Ps2Controller::ENABLE_SECOND_PORT if self.dual_channel => {
+                    self.set_config_bits(1 << 5, false)
+                }
+                _ => {}
+            }
         }
     }
+
+    test_case!(ps2_controller, {
+        // Bytes left over from the firmware are flushed
+        let controller = FakeController::new(true);
+        controller
+            .output
+            .borrow_mut()
+            .extend([(Ps2Port::First, 0xFA), (Ps2Port::Second, 0xAA)]);
+        test_assert_eq!(
+            Ok(([true, true], true)),
+            Ps2Controller::initialize(&controller)
+        );
+        // Both interrupts on, translation off
+        test_assert_eq!(0x03, controller.config.get());
+        test_assert!(controller.output.borrow().is_empty());
+
+        // Without a second port its clock stays disabled
+        let controller = FakeController::new(false);
+        controller.config.set(0x63);
+        test_assert_eq!(
+            Ok(([true, false], false)),
+            Ps2Controller::initialize(&controller)
+        );
+        test_assert_eq!(0x21, controller.config.get());
+
+        let controller = FakeController {
+            self_test: 0xFC,
+            ..FakeController::new(true)
+        };
+        test_assert_eq!(
+            Err(Ps2Error::SelfTestFailed(0xFC)),
+            Ps2Controller::initialize(&controller)
+        );
+
+        // A controller that never accepts a byte times out
+        let controller = FakeController {
+            busy: true,
+            ..FakeController::new(true)
+        };
+        test_assert_eq!(
+            Err(Ps2Error::Timeout),
+            Ps2Controller::initialize(&controller)
+        );
+
+        // A device that keeps sending does not stall the flush
+        let controller = FakeController::new(true);
+        controller
+            .output
+            .borrow_mut()
+            .extend([(Ps2Port::First, 0); 20]);
+        controller.flush();
+        test_assert_eq!(4, controller.output.borrow().len());
+    });
+
+    test_case!(ps2_drain_routing, {
+        let controller = FakeController::new(true);
+        let incoming = [CircularBuffer::new(4), CircularBuffer::new(4)];
+        controller.output.borrow_mut().extend([
+            (Ps2Port::Second, 0x08),
+            (Ps2Port::First, 0x1C),
+            (Ps2Port::Second, 0x01),
+        ]);
+        // A byte of the other port does not stop the drain
+        controller.drain_into(&incoming);
+        test_assert!(controller.output.borrow().is_empty());
+        test_assert_eq!(Some(0x1C), incoming[0].read());
+        test_assert_eq!(None, incoming[0].read());
+        test_assert_eq!(Some(0x08), incoming[1].read());
+        test_assert_eq!(Some(0x01), incoming[1].read());
+        test_assert_eq!(None, incoming[1].read());
+    });
+
+    test_case!(ps2_scan_code_set_2, {
+        let key_code = |scan_code| KeyCode::from(ScanCode(scan_code));
+        test_assert_eq!(KeyCode::KeyA, key_code(0x1C));
+        test_assert_eq!(KeyCode::Digit0, key_code(0x45));
+        test_assert_eq!(KeyCode::F7, key_code(0x83));
+        test_assert_eq!(KeyCode::Numpad9, key_code(0x7D));
+        test_assert_eq!(KeyCode::ShiftRight, key_code(0x59));
+
+        // Every key has exactly one make code
+        let mut seen = Vec::new();
+        for scan_code in 0..=0x83 {
+            let key_code = key_code(scan_code);
+            if key_code != KeyCode::Unknown {
+                test_assert!(!seen.contains(&key_code));
+                seen.push(key_code);
+            }
+        }
+        for scan_code in 0..=0xFF {
+            let Some(key_code) = ExtendedScanCode(scan_code).key_code() else {
+                continue;
+            };
+            if key_code != KeyCode::Unknown {
+                test_assert!(!seen.contains(&key_code));
+                seen.push(key_code);
+            }
+        }
+        // Pause has no scan code of its own
+        seen.push(KeyCode::Pause);
+        test_assert_eq!(KeyCode::Unknown as usize, seen.len());
+    });
+
+    test_case!(ps2_key_translator, {
+        let mut translator = KeyTranslator::default();
+        let mut press = |key_code| {
+            translator
+                .translate(KeyboardInput::new(key_code, KeyState::Pressed))
+                .character
+        };
+
+        test_assert_eq!(Some('a'), press(KeyCode::KeyA));
+        test_assert_eq!(Some('1'), press(KeyCode::Digit1));
+        test_assert_eq!(None, press(KeyCode::ArrowUp));
+        test_assert_eq!(None, press(KeyCode::Numpad7));
+        test_assert_eq!(Some('\n'), press(KeyCode::NumpadEnter));
+
+        test_assert_eq!(None, press(KeyCode::CapsLock));
+
+        let mut translator = KeyTranslator::default();
+        let mut input = |key_code, state| translator.translate(KeyboardInput::new(key_code, state));
+        input(KeyCode::CapsLock, KeyState::Pressed);
+        // A typematic repeat of Caps Lock does not toggle it back
+        input(KeyCode::CapsLock, KeyState::Pressed);
+        input(KeyCode::CapsLock, KeyState::Released);
+        test_assert_eq!(Some('A'), input(KeyCode::KeyA, KeyState::Pressed).character);
+        test_assert_eq!(None, input(KeyCode::KeyA, KeyState::Released).character);
+
+        let shift = input(KeyCode::ShiftRight, KeyState::Pressed);
+        test_assert!(shift.modifiers.shift());
+        test_assert!(shift.modifiers.caps_lock);
+        test_assert_eq!(Some('a'), input(KeyCode::KeyA, KeyState::Pressed).character);
+        test_assert_eq!(
+            Some('!'),
+            input(KeyCode::Digit1, KeyState::Pressed).character
+        );
+        test_assert_eq!(
+            Some('"'),
+            input(KeyCode::Quote, KeyState::Pressed).character
+        );
+        let released = input(KeyCode::ShiftRight, KeyState::Released);
+        test_assert!(!released.modifiers.shift());
+
+        input(KeyCode::ControlLeft, KeyState::Pressed);
+        test_assert_eq!(
+            Some('\u{3}'),
+            input(KeyCode::KeyC, KeyState::Pressed).character
+        );
+        input(KeyCode::ControlLeft, KeyState::Released);
+
+        input(KeyCode::AltLeft, KeyState::Pressed);
+        test_assert_eq!(None, input(KeyCode::KeyF, KeyState::Pressed).character);
+        input(KeyCode::AltLeft, KeyState::Released);
+
+        input(KeyCode::NumLock, KeyState::Pressed);
+        input(KeyCode::NumLock, KeyState::Released);
+        let seven = input(KeyCode::Numpad7, KeyState::Pressed);
+        test_assert!(seven.modifiers.num_lock);
+        test_assert_eq!(Some('7'), seven.character);
+    });
+
+    test_case!(ps2_typematic, {
+        test_assert_eq!(Duration::from_millis(500), Typematic::DEFAULT.delay());
+        test_assert_eq!(Duration::from_micros(91_674), Typematic::DEFAULT.period());
+        test_assert_eq!(
+            Typematic::DEFAULT,
+            Typematic::new(Duration::from_millis(500), Duration::from_millis(92))
+        );
+        test_assert_eq!(
+            Typematic(0x00),
+            Typematic::new(Duration::ZERO, Duration::from_millis(1))
+        );
+        test_assert_eq!(
+            Typematic(0x7F),
+            Typematic::new(Duration::from_secs(5), Duration::from_secs(1))
+        );
+        test_assert_eq!(Some(0xF3), Command::SetTypematic(Typematic(0x7F)).byte(0));
+        test_assert_eq!(Some(0x7F), Command::SetTypematic(Typematic(0x7F)).byte(1));
+
+        let mut translator = KeyTranslator::default();
+        let mut input = |key_code, state| translator.translate(KeyboardInput::new(key_code, state));
+        test_assert!(!input(KeyCode::KeyA, KeyState::Pressed).repeat);
+        let repeat = input(KeyCode::KeyA, KeyState::Pressed);
+        test_assert!(repeat.repeat);
+        test_assert_eq!(Some('a'), repeat.character);
+        test_assert!(!input(KeyCode::KeyA, KeyState::Released).repeat);
+        test_assert!(!input(KeyCode::KeyA, KeyState::Pressed).repeat);
+        test_assert!(!input(KeyCode::Unknown, KeyState::Pressed).repeat);
+
+        test_assert!(KeyCode::CapsLock.is_modifier());
+        test_assert!(!KeyCode::KeyA.is_modifier());
+    });
+
+    test_case!(ps2_software_repeat, {
+        // A private wheel on a clock with 1 ms ticks that only advances by hand
+        static NOW_MS: AtomicU64 = AtomicU64::new(0);
+        let timers: &'static TimerWheel = Box::leak(Box::new(TimerWheel::with_clock(None, || {
+            let tick = NOW_MS.load(Ordering::Acquire);
+            Some(WheelTime {
+                tick,
+                ns: tick * 1_000_000,
+                tick_period_ns: 1_000_000,
+            })
+        })));
+        let advance_to = |ms| {
+            NOW_MS.store(ms, Ordering::Release);
+            timers.run_expired();
+        };
+        let output = Arc::new(CircularBuffer::new(8));
+        let drain = || {
+            let mut inputs = Vec::new();
+            while let Some(input) = output.read() {
+                inputs.push(input);
+            }
+            inputs
+        };
+
+        let mut repeater = SoftwareRepeat::new(timers);
+        let press = KeyboardInput::new(KeyCode::KeyA, KeyState::Pressed);
+        repeater.update(&press, Typematic::DEFAULT, &output);
+        // Modifiers do not repeat, nor stop the repeating key
+        let shift = KeyboardInput::new(KeyCode::ShiftLeft, KeyState::Pressed);
+        repeater.update(&shift, Typematic::DEFAULT, &output);
+
+        advance_to(499);
+        test_assert!(drain().is_empty());
+
+        // The first repeat after the 500 ms delay, then one every 91.674 ms
+        advance_to(500);
+        let repeats = drain();
+        test_assert_eq!(1, repeats.len());
+        test_assert_eq!(KeyCode::KeyA, repeats[0].key_code);
+        test_assert!(repeats[0].repeat);
+        for tick in [592, 684] {
+            advance_to(tick - 1);
+            test_assert!(drain().is_empty());
+            advance_to(tick);
+            test_assert_eq!(1, drain().len());
+        }
+
+        let release = KeyboardInput::new(KeyCode::KeyA, KeyState::Released);
+        repeater.update(&release, Typematic::DEFAULT, &output);
+        test_assert!(repeater.repeating.is_none());
+        advance_to(2_000);
+        test_assert!(drain().is_empty());
+
+        // A new key takes over, releasing the old one does not stop it
+        repeater.update(&press, Typematic::DEFAULT, &output);
+        let other = KeyboardInput::new(KeyCode::KeyB, KeyState::Pressed);
+        repeater.update(&other, Typematic::DEFAULT, &output);
+        repeater.update(&release, Typematic::DEFAULT, &output);
+        advance_to(2_500);
+        let repeats = drain();
+        test_assert_eq!(1, repeats.len());
+        test_assert_eq!(KeyCode::KeyB, repeats[0].key_code);
+        repeater.stop();
+    });
+
+    test_case!(ps2_scan_code_decoder, {
+        let mut decoder = ScanCodeDecoder::default();
+        let mut decode = |bytes: &[u8]| -> Vec<KeyboardInput> {
+            bytes
+                .iter()
+                .filter_map(|byte| decoder.feed(*byte))
+                .collect()
+        };
+        let pressed = |key_code| KeyboardInput::new(key_code, KeyState::Pressed);
+        let released = |key_code| KeyboardInput::new(key_code, KeyState::Released);
+
+        test_assert_eq!(
+            [pressed(KeyCode::KeyA), released(KeyCode::KeyA)],
+            decode(&[0x1C, 0xF0, 0x1C])[..]
+        );
+        test_assert_eq!(
+            [pressed(KeyCode::ArrowUp), released(KeyCode::ArrowUp)],
+            decode(&[0xE0, 0x75, 0xE0, 0xF0, 0x75])[..]
+        );
+        test_assert_eq!(
+            [
+                pressed(KeyCode::ControlRight),
+                pressed(KeyCode::ControlLeft)
+            ],
+            decode(&[0xE0, 0x14, 0x14])[..]
+        );
+        test_assert_eq!(
+            [
+                pressed(KeyCode::PrintScreen),
+                released(KeyCode::PrintScreen)
+            ],
+            decode(&[0xE0, 0x12, 0xE0, 0x7C, 0xE0, 0xF0, 0x7C, 0xE0, 0xF0, 0x12])[..]
+        );
+        test_assert_eq!(
+            [pressed(KeyCode::Pause), released(KeyCode::Pause)],
+            decode(&[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77])[..]
+        );
+
+        // Command responses between keys are skipped
+        test_assert_eq!([pressed(KeyCode::KeyW)], decode(&[0xFA, 0xAA, 0x1D])[..]);
+        test_assert_eq!([pressed(KeyCode::Unknown)], decode(&[0xE0, 0x10])[..]);
+    });
+
+    test_case!(ps2_command_queue, {
+        let mut queue = CommandQueue::default();
+        let mut sent = Vec::new();
+        let now = Instant::from_nanos(0);
+        let leds = Leds {
+            caps_lock: true,
+            ..Leds::default()
+        };
+
+        let id = queue
+            .push(Command::SetLeds(leds), true, now, |byte| sent.push(byte))
+            .unwrap()
+            .unwrap();
+        let echo = queue
+            .push(Command::Echo, true, now, |byte| sent.push(byte))
+            .unwrap()
+            .unwrap();
+        // Only the first command is in flight
+        test_assert_eq!([0xED][..], sent[..]);
+
+        // Scan codes are not responses
+        test_assert!(!queue.receive(0x1C, now, |byte| sent.push(byte)));
+        test_assert!(queue.receive(0xFE, now, |byte| sent.push(byte)));
+        test_assert!(queue.receive(0xFA, now, |byte| sent.push(byte)));
+        test_assert!(queue.receive(0xFA, now, |byte| sent.push(byte)));
+        test_assert_eq!([0xED, 0xED, 0x04, 0xEE][..], sent[..]);
+        test_assert_eq!(Some(Ok(Response::new())), queue.take_result(id));
+        test_assert_eq!(None, queue.take_result(id));
+
+        test_assert!(queue.receive(0xEE, now, |byte| sent.push(byte)));
+        test_assert_eq!(Ok(&[0xEE][..]), queue.take_result(echo).unwrap().as_deref());
+        test_assert!(queue.is_idle());
+        test_assert!(!queue.receive(0xFA, now, |byte| sent.push(byte)));
+
+        // Too many resends fail the command
+        let id = queue
+            .push(Command::DisableScanning, true, now, |_| {})
+            .unwrap()
+            .unwrap();
+        for _ in 0..=CommandQueue::MAX_RESENDS {
+            queue.receive(0xFE, now, |_| {});
+        }
+        test_assert_eq!(Some(Err(CommandError::Resend)), queue.take_result(id));
+
+        // A byte the device answers with an error fails the command
+        let id = queue
+            .push(Command::SetSampleRate(7), true, now, |_| {})
+            .unwrap()
+            .unwrap();
+        test_assert!(queue.receive(0xFA, now, |_| {}));
+        test_assert!(queue.receive(0xFC, now, |_| {}));
+        test_assert_eq!(Some(Err(CommandError::Rejected)), queue.take_result(id));
+        test_assert!(queue.is_idle());
+        // The mouse does not even send sample rates it does not accept
+        test_assert_eq!(
+            Err(CommandError::InvalidSampleRate(7)),
+            Ps2Mouse::check_sample_rate(7)
+        );
+        test_assert_eq!(Ok(()), Ps2Mouse::check_sample_rate(200));
+
+        // Identification may end early, by timing out
+        let id = queue
+            .push(Command::Identify, true, now, |_| {})
+            .unwrap()
+            .unwrap();
+        queue.receive(0xFA, now, |_| {});
+        queue.receive(0xAB, now, |_| {});
+        queue.check_timeout(now, |_| {});
+        test_assert_eq!(None, queue.take_result(id));
+        queue.check_timeout(now + Duration::from_millis(20), |_| {});
+        test_assert_eq!(Ok(&[0xAB][..]), queue.take_result(id).unwrap().as_deref());
+
+        let id = queue
+            .push(Command::Reset, true, now, |_| {})
+            .unwrap()
+            .unwrap();
+        queue.check_timeout(now + Duration::from_secs(1), |_| {});
+        test_assert_eq!(Some(Err(CommandError::Timeout)), queue.take_result(id));
+
+        // Polling also times out while the clock stands still
+        let id = queue
+            .push(Command::Echo, true, now, |_| {})
+            .unwrap()
+            .unwrap();
+        for _ in 1..poll_budget(Command::Echo.timeout()) {
+            queue.check_timeout_polling(now, |_| {});
+        }
+        test_assert_eq!(None, queue.take_result(id));
+        queue.check_timeout_polling(now, |_| {});
+        test_assert_eq!(Some(Err(CommandError::Timeout)), queue.take_result(id));
+
+        // Commands nobody waits for leave no result
+        test_assert_eq!(
+            Ok(None),
+            queue.push(Command::EnableScanning, false, now, |_| {})
+        );
+        queue.receive(0xFA, now, |_| {});
+        test_assert!(queue.is_idle());
+        test_assert!(queue.results.is_empty());
+
+        // Nor do they hold up later commands when the device never answers
+        let mut sent = Vec::new();
+        queue
+            .push(Command::SetLeds(leds), false, now, |byte| sent.push(byte))
+            .unwrap();
+        let later = now + Command::SetLeds(leds).timeout();
+        let echo = queue
+            .push(Command::Echo, true, later, |byte| sent.push(byte))
+            .unwrap()
+            .unwrap();
+        test_assert_eq!([0xED, 0xEE][..], sent[..]);
+        test_assert!(queue.receive(0xEE, later, |_| {}));
+        test_assert_eq!(Ok(&[0xEE][..]), queue.take_result(echo).unwrap().as_deref());
+    });
+
+    test_case!(ps2_mouse_packets, {
+        let mut decoder = MousePacketDecoder::new(MouseKind::Standard);
+        let mut decode = |bytes: &[u8]| -> Vec<MouseInput> {
+            bytes
+                .iter()
+                .filter_map(|byte| decoder.feed(*byte))
+                .collect()
+        };
+
+        // Left button, 5 to the right and 3 up
+        let input = decode(&[0x09, 0x05, 0x03]);
+        test_assert_eq!(1, input.len());
+        test_assert_eq!(5, input[0].dx);
+        test_assert_eq!(-3, input[0].dy);
+        test_assert!(input[0].pressed(MouseButtons::LEFT));
+
+        // Negative motion, left button still held
+        let input = decode(&[0x39, 0xFE, 0xFF]);
+        test_assert_eq!(-2, input[0].dx);
+        test_assert_eq!(1, input[0].dy);
+        test_assert!(input[0].buttons.contains(MouseButtons::LEFT));
+        test_assert!(input[0].changed.is_empty());
+
+        // Out of sync bytes are dropped, overflowed axes discarded
+        let input = decode(&[0x05, 0x08 | 0x40, 0x80, 0x04]);
+        test_assert_eq!(0, input[0].dx);
+        test_assert_eq!(-4, input[0].dy);
+        test_assert!(input[0].released(MouseButtons::LEFT));
+
+        let mut decoder = MousePacketDecoder::new(MouseKind::Wheel);
+        test_assert_eq!(None, decoder.feed(0x0C));
+        decoder.feed(0x00);
+        decoder.feed(0x00);
+        let input = decoder.feed(0xFF).unwrap();
+        test_assert_eq!(-1, input.wheel);
+        test_assert!(input.pressed(MouseButtons::MIDDLE));
+
+        let mut decoder = MousePacketDecoder::new(MouseKind::FiveButton);
+        let input = [0x08, 0x00, 0x00, 0x2F]
+            .iter()
+            .find_map(|byte| decoder.feed(*byte))
+            .unwrap();
+        test_assert_eq!(-1, input.wheel);
+        test_assert!(input.pressed(MouseButtons::FORWARD));
+        test_assert!(!input.buttons.contains(MouseButtons::BACK));
+    });
 }
@@ -1,4 +1,4 @@
-use crate::port::{PortManager, PortSlice};
+use crate::port::{Port, PortManager, PortSlice};
 
 pub struct SerialPort {
     ports: PortSlice<8>,
@@ -61,3 +61,19 @@ impl SerialPort {
         unsafe { self.ports[0].write(byte) };
     }
 }
+
+/// Polls COM1 directly, bypassing the [`PortManager`] and the logger.
+///
+/// Only meant for reporting from a state where the logger cannot be trusted, e.g. a panic or a
+/// hang detected from an NMI.
+pub struct RawSerialWriter;
+
+impl core::fmt::Write for RawSerialWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.bytes() {
+            while unsafe { Port::new(0x3FD).read() & 0x20 } == 0 {}
+            unsafe { Port::new(0x3F8).write(c) };
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    clock::{self, Duration},
+    lock::spinlock::SpinLock,
+    pit::{Pit, PitChannel2},
+    port::PortManager,
+    system_control::SYSTEM_CONTROL_B,
+    timer::{self, TimerError, TimerHandle},
+};
+use alloc::sync::Arc;
+
+/// A tone of `frequency` Hz held for `duration`, or a rest if `frequency` is zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub frequency: u32,
+    pub duration: Duration,
+}
+
+impl Note {
+    pub const fn new(frequency: u32, millis: u64) -> Self {
+        Self {
+            frequency,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    pub const fn rest(millis: u64) -> Self {
+        Self::new(0, millis)
+    }
+}
+
+/// PC speaker driven by PIT channel 2.
+///
+/// [`https://wiki.osdev.org/PC_Speaker`]
+pub struct Speaker {
+    channel2: Arc<SpinLock<PitChannel2>>,
+    /// The timer ending the current note of [`Self::play_async`].
+    melody: Arc<SpinLock<Option<TimerHandle>>>,
+}
+
+impl Speaker {
+    const ENABLE: u8 = 0b11;
+
+    pub const BEEP: Note = Note::new(1000, 100);
+
+    pub fn new(port_manager: &mut PortManager, pit: &Pit) -> Self {
+        let channel2 = pit
+            .channel2(port_manager)
+            .expect("only one Speaker driver may be active");
+
+        Self {
+            channel2: Arc::new(SpinLock::new(channel2)),
+            melody: Arc::new(SpinLock::new(None)),
+        }
+    }
+
+    /// Starts playing `frequency` Hz until [`Self::stop`], cutting off [`Self::play_async`].
+    pub fn start(&mut self, frequency: u32) {
+        self.cancel_melody();
+        Self::set_tone(&self.channel2, frequency);
+    }
+
+    pub fn stop(&mut self) {
+        self.cancel_melody();
+        Self::set_tone(&self.channel2, 0);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        SYSTEM_CONTROL_B.read() & Self::ENABLE == Self::ENABLE
+    }
+
+    /// Plays `frequency` Hz for `duration`, blocking meanwhile.
+    pub fn tone(&mut self, frequency: u32, duration: Duration) {
+        self.start(frequency);
+        clock::sleep(duration);
+        self.stop();
+    }
+
+    pub fn beep(&mut self) {
+        self.play(&[Self::BEEP]);
+    }
+
+    /// Plays `notes` back to back, blocking until the last one ends. Used where timers cannot
+    /// fire, e.g. with interrupts disabled, otherwise prefer [`Self::play_async`].
+    pub fn play(&mut self, notes: &[Note]) {
+        for note in notes {
+            self.tone(note.frequency, note.duration);
+        }
+    }
+
+    /// Starts playing `notes` back to back and returns immediately, each note is ended by a
+    /// timer. Cuts off a melody that is still playing.
+    pub fn play_async(&mut self, notes: &[Note]) -> Result<(), TimerError> {
+        self.cancel_melody();
+        Self::play_from(self.channel2.clone(), self.melody.clone(), notes.into(), 0)
+    }
+
+    fn play_from(
+        channel2: Arc<SpinLock<PitChannel2>>,
+        melody: Arc<SpinLock<Option<TimerHandle>>>,
+        notes: Arc<[Note]>,
+        index: usize,
+    ) -> Result<(), TimerError> {
+        let Some(note) = notes.get(index).copied() else {
+            Self::set_tone(&channel2, 0);
+            *melody.lock() = None;
+            return Ok(());
+        };
+
+        Self::set_tone(&channel2, note.frequency);
+        let next_channel2 = channel2.clone();
+        let next_melody = melody.clone();
+        let next = timer::after(note.duration, move || {
+            let _ = Self::play_from(
+                next_channel2.clone(),
+                next_melody.clone(),
+                notes.clone(),
+                index + 1,
+            );
+        });
+        match next {
+            Ok(next) => {
+                *melody.lock() = Some(next);
+                Ok(())
+            }
+            Err(err) => {
+                Self::set_tone(&channel2, 0);
+                Err(err)
+            }
+        }
+    }
+
+    fn cancel_melody(&mut self) {
+        if let Some(timer) = self.melody.lock().take() {
+            timer.cancel();
+        }
+    }
+
+    fn set_tone(channel2: &SpinLock<PitChannel2>, frequency: u32) {
+        if frequency == 0 {
+            SYSTEM_CONTROL_B.update(|control| control & !Self::ENABLE);
+            return;
+        }
+
+        channel2.lock().set_square_wave(frequency);
+        SYSTEM_CONTROL_B.update(|control| control | Self::ENABLE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test, test_case};
+
+    test_case!(speaker, {
+        let mut speaker = Speaker::new(&mut PortManager::default(), test::pit());
+
+        speaker.start(Pit::BASE_FREQUENCY / 1000);
+        test_assert!(speaker.is_playing());
+        // The count of a running channel keeps changing, within the divisor
+        let first = speaker.channel2.lock().read_count();
+        let mut second = first;
+        for _ in 0..10_000 {
+            second = speaker.channel2.lock().read_count();
+            if second != first {
+                break;
+            }
+        }
+        test_assert!(first != second);
+        test_assert!(second <= 1000);
+
+        speaker.start(0);
+        test_assert!(!speaker.is_playing());
+
+        speaker.start(440);
+        speaker.stop();
+        test_assert!(!speaker.is_playing());
+
+        // Interrupts are disabled, so only the first note starts and the melody never advances
+        test_assert!(speaker
+            .play_async(&[Note::new(440, 1000), Note::rest(10)])
+            .is_ok());
+        test_assert!(speaker.is_playing());
+        test_assert!(speaker
+            .melody
+            .lock()
+            .as_ref()
+            .is_some_and(|timer| timer.is_active()));
+        speaker.stop();
+        test_assert!(!speaker.is_playing());
+        test_assert!(speaker.melody.lock().is_none());
+
+        test_assert!(speaker.play_async(&[]).is_ok());
+        test_assert!(!speaker.is_playing());
+    });
+}
@@ -0,0 +1,30 @@
+use crate::{interrupt, port::Port};
+
+/// System control port A, bit 4 reports the watchdog timer as an NMI cause.
+pub static SYSTEM_CONTROL_A: SystemControl = SystemControl(unsafe { Port::new(0x92) });
+
+/// System control port B.
+///
+/// Bit 0 gates PIT channel 2 and bit 1 connects it to the PC speaker, bits 2 and 3 clear and
+/// disable the parity and channel check NMIs, and bits 6 and 7 report those NMIs. Shared by
+/// [`crate::speaker::Speaker`] and [`crate::nmi::handle_nmi`], which has no driver to go through.
+///
+/// [`https://wiki.osdev.org/Non_Maskable_Interrupt`]
+pub static SYSTEM_CONTROL_B: SystemControl = SystemControl(unsafe { Port::new(0x61) });
+
+/// A system control port, reserved in [`crate::port::PortManager`] so that no driver owns it.
+pub struct SystemControl(Port);
+
+impl SystemControl {
+    pub fn read(&self) -> u8 {
+        unsafe { self.0.read() }
+    }
+
+    /// Read-modify-write of the writable lower four bits, the upper ones read back status.
+    pub fn update(&self, f: impl FnOnce(u8) -> u8) {
+        interrupt::without_interrupts(|| unsafe {
+            let control = self.0.read() & 0x0F;
+            self.0.write(f(control) & 0x0F);
+        });
+    }
+}
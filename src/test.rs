@@ -53,6 +53,34 @@ pub enum TestResult {
     Failure(usize),
 }
 
+/// The PIT shared by every test that needs one, started at 1 kHz on first use so its handler is
+/// only registered once.
+#[cfg(test)]
+pub fn pit() -> &'static crate::pit::Pit {
+    use crate::{interrupt::INTERRUPT_LOOKUP, pic::Pic, pit::Pit, port::PortManager};
+    use alloc::boxed::Box;
+    use core::{
+        ptr,
+        sync::atomic::{AtomicPtr, Ordering},
+    };
+
+    static PIT: AtomicPtr<Pit> = AtomicPtr::new(ptr::null_mut());
+
+    let mut pit = PIT.load(Ordering::Acquire);
+    if pit.is_null() {
+        let mut port_manager = PortManager::default();
+        let mut pic = Pic::new(&mut port_manager);
+        pit = Box::leak(Box::new(Pit::new(
+            &mut port_manager,
+            &INTERRUPT_LOOKUP,
+            &mut pic,
+            1000,
+        )));
+        PIT.store(pit, Ordering::Release);
+    }
+    unsafe { &*pit }
+}
+
 pub fn test_runner(tests: &[&TestFn]) {
     use crate::exit::{exit_qemu, QemuExitCode};
     use alloc::vec::Vec;
@@ -1,9 +1,19 @@
 use crate::{
+    acpi,
+    deferred::DEFERRED_WORK,
     info,
-    interrupt::{InterruptHandler, InterruptLookup, IrqId, PicHandler},
+    interrupt::{self, InterruptHandler, InterruptLookup, IrqId, PicHandler},
+    lock::spinlock::SpinLock,
+    nmi,
     pic::Pic,
     port::{Port, PortManager},
 };
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
 
 pub struct Cmos {
     pub register_select: Port,
@@ -21,7 +31,8 @@ impl Cmos {
         }
     }
 
-    /// Lacks inner-second precision. May sleep for `0 < 1` more or less than expected.
+    /// Lacks inner-second precision. May sleep for `0 < 1` more or less than expected, see
+    /// [`crate::clock::sleep`] for precise sleeps.
     pub fn sleep(&self, mut secs: usize) {
         let mut last_second = self.second();
         loop {
@@ -69,103 +80,394 @@ impl Cmos {
         }
 
         let reg_b = self.read_register(0x0B);
-        if (reg_b & 0x04) == 0 && reg != 0x04 {
-            val = (val & 0x0F) + ((val / 16) * 10);
-        } else if (reg_b & 0x04 == 0) && reg == 0x04 {
-            val = ((val & 0x0F) + (((val & 0x70) / 16) * 10)) | (val & 0x80);
+        if reg == 0x04 {
+            val = decode_hour(val, reg_b);
+        } else {
+            val = decode_binary(val, reg_b);
         }
 
         val
     }
 
     pub fn get_rtc(&self) -> Rtc {
-        while self.update_in_progress() {
-            info!("Cmos update in progress, spinning...");
-        }
-        let mut second = self.read_register(0x00);
-        let mut minute = self.read_register(0x02);
-        let mut hour = self.read_register(0x04);
-        let mut day = self.read_register(0x07);
-        let mut month = self.read_register(0x08);
-        let mut year = self.read_register(0x09);
+        let century_register = *CENTURY_REGISTER;
+        let read_all = || {
+            while self.update_in_progress() {
+                info!("Cmos update in progress, spinning...");
+            }
+            RawRtc {
+                second: self.read_register(0x00),
+                minute: self.read_register(0x02),
+                hour: self.read_register(0x04),
+                day: self.read_register(0x07),
+                month: self.read_register(0x08),
+                year: self.read_register(0x09),
+                century: century_register.map(|register| self.read_register(register)),
+            }
+        };
 
         // NOTE: In practice, this never actually necessary, but it is a much more robust solution to
         // prevent one time bugs.
-        //
-        // let mut loops = 0;
+        let mut raw = read_all();
         loop {
-            while self.update_in_progress() {
-                info!("Cmos update in progress, spinning...");
-            }
-            let last_second = self.read_register(0x00);
-            let last_minute = self.read_register(0x02);
-            let last_hour = self.read_register(0x04);
-            let last_day = self.read_register(0x07);
-            let last_month = self.read_register(0x08);
-            let last_year = self.read_register(0x09);
-
-            if last_second != second
-                || last_minute != minute
-                || last_hour != hour
-                || last_day != day
-                || last_month != month
-                || last_year != year
-            {
-                second = last_second;
-                minute = last_minute;
-                hour = last_hour;
-                day = last_day;
-                month = last_month;
-                year = last_year;
-                // loops += 1;
+            let last = read_all();
+            if last != raw {
+                raw = last;
             } else {
-                // serial_println!("rtc fetch took {} confirm read", loops + 1);
                 break;
             }
         }
 
         let reg_b = self.read_register(0x0B);
-        let bcd_to_dec = |bcd: &mut u8| *bcd = (*bcd & 0x0F) + ((*bcd / 16) * 10);
-        if (reg_b & 0x04) == 0 {
-            bcd_to_dec(&mut second);
-            bcd_to_dec(&mut minute);
-            hour = ((hour & 0x0F) + (((hour & 0x70) / 16) * 10)) | (hour & 0x80);
-            bcd_to_dec(&mut day);
-            bcd_to_dec(&mut month);
-            bcd_to_dec(&mut year);
-        }
+        let decode = |value: u8| decode_binary(value, reg_b);
 
         Rtc {
-            second,
-            minute,
-            hour,
-            day,
-            month,
-            year,
+            second: decode(raw.second),
+            minute: decode(raw.minute),
+            hour: decode_hour(raw.hour, reg_b),
+            day: decode(raw.day),
+            month: decode(raw.month),
+            year: decode(raw.year),
+            century: raw.century.map(decode),
         }
     }
 
+    /// The current wall-clock time, assuming the RTC runs in UTC.
+    pub fn datetime(&self) -> DateTime {
+        self.get_rtc().datetime()
+    }
+
     pub fn read_register(&self, register: u8) -> u8 {
-        self.select_register(register);
-        unsafe { self.data.read() }
+        // The RTC interrupt handler selects register C, so selecting and accessing the register
+        // must not be interrupted.
+        interrupt::without_interrupts(|| {
+            self.select_register(register);
+            unsafe { self.data.read() }
+        })
+    }
+
+    /// Read-modify-write of `register` with NMIs disabled, so that the RTC is not left in an
+    /// undefined state.
+    fn update_register(&self, register: u8, f: impl FnOnce(u8) -> u8) {
+        interrupt::without_interrupts(|| unsafe {
+            self.register_select.write(0x80 | register);
+            let value = self.data.read();
+            self.register_select.write(0x80 | register);
+            self.data.write(f(value));
+
+            // Restores the NMI state
+            self.select_register(0x0D);
+        });
+    }
+
+    fn write_register(&self, register: u8, value: u8) {
+        self.update_register(register, |_| value);
+    }
+
+    /// Changes the rate of the periodic interrupt, keeping [`rtc_uptime_ns`] continuous.
+    pub fn set_periodic_rate(&self, rate: RtcRate) {
+        interrupt::without_interrupts(|| {
+            self.update_register(0x0A, |reg_a| (reg_a & 0xF0) | rate.0);
+
+            if let Some(uptime) = rtc_uptime_ns() {
+                RTC_EPOCH_NS.store(uptime, Ordering::Release);
+                RTC_EPOCH_TICKS.store(rtc_ticks(), Ordering::Release);
+                RTC_FREQUENCY.store(rate.frequency(), Ordering::Release);
+            }
+        });
+    }
+
+    /// Sets the RTC to `datetime`, which is converted to UTC first.
+    ///
+    /// Updates are frozen with the SET bit while the registers are written, and values are
+    /// encoded in the RTC's own BCD or binary and 12 or 24 hour format.
+    pub fn set_datetime(&self, datetime: &DateTime) -> Result<(), SetTimeError> {
+        if !datetime.is_valid() {
+            return Err(SetTimeError::InvalidDateTime);
+        }
+
+        let datetime = datetime.to_offset(UtcOffset::UTC);
+        let century_register = *CENTURY_REGISTER;
+        // Without a century register the year is read back as 1970-2069, see `Rtc::full_year`
+        if century_register.is_none() && !(1970..2070).contains(&datetime.year) {
+            return Err(SetTimeError::YearOutOfRange);
+        }
+        if datetime.year > 9999 {
+            return Err(SetTimeError::YearOutOfRange);
+        }
+
+        interrupt::without_interrupts(|| {
+            self.update_register(0x0B, |reg_b| reg_b | 0x80);
+            let reg_b = self.read_register(0x0B);
+            let encode = |value: u8| encode_binary(value, reg_b);
+
+            self.write_register(0x00, encode(datetime.second));
+            self.write_register(0x02, encode(datetime.minute));
+            self.write_register(0x04, encode_hour(datetime.hour, reg_b));
+            self.write_register(0x06, encode(datetime.weekday()));
+            self.write_register(0x07, encode(datetime.day));
+            self.write_register(0x08, encode(datetime.month));
+            self.write_register(0x09, encode((datetime.year % 100) as u8));
+            if let Some(register) = century_register {
+                self.write_register(register, encode((datetime.year / 100) as u8));
+            }
+
+            self.update_register(0x0B, |reg_b| reg_b & !0x80);
+        });
+
+        Ok(())
+    }
+
+    /// Sets the alarm, replacing any previous one. `callback` runs in deferred context every time
+    /// the RTC reaches `time`, once [`Rtc::enable_irq`] installed the interrupt handler.
+    ///
+    /// Must not be called from an interrupt handler.
+    pub fn set_alarm(
+        &self,
+        time: AlarmTime,
+        callback: impl FnMut() + 'static,
+    ) -> Result<(), SetTimeError> {
+        if !time.is_valid() {
+            return Err(SetTimeError::InvalidAlarmTime);
+        }
+
+        *ALARM_CALLBACK.lock() = Some(Box::new(callback));
+        ALARM_ARMED.store(true, Ordering::Release);
+
+        let reg_b = self.read_register(0x0B);
+        let encode = |value: Option<u8>, to_register: fn(u8, u8) -> u8| match value {
+            Some(value) => to_register(value, reg_b),
+            None => AlarmTime::ANY,
+        };
+
+        interrupt::without_interrupts(|| {
+            self.write_register(0x01, encode(time.second, encode_binary));
+            self.write_register(0x03, encode(time.minute, encode_binary));
+            self.write_register(0x05, encode(time.hour, encode_hour));
+            self.update_register(0x0B, |reg_b| reg_b | 0x20);
+        });
+
+        Ok(())
+    }
+
+    pub fn clear_alarm(&self) {
+        self.update_register(0x0B, |reg_b| reg_b & !0x20);
+        ALARM_ARMED.store(false, Ordering::Release);
+        ALARM_CALLBACK.lock().take();
+    }
+
+    pub fn alarm(&self) -> Option<AlarmTime> {
+        let reg_b = self.read_register(0x0B);
+        if reg_b & 0x20 == 0 {
+            return None;
+        }
+
+        let decode = |value: u8, from_register: fn(u8, u8) -> u8| {
+            (value < AlarmTime::ANY).then(|| from_register(value, reg_b))
+        };
+        Some(AlarmTime {
+            hour: decode(self.read_register(0x05), decode_hour),
+            minute: decode(self.read_register(0x03), decode_binary),
+            second: decode(self.read_register(0x01), decode_binary),
+        })
+    }
+
+    pub fn periodic_rate(&self) -> Option<RtcRate> {
+        RtcRate::from_value(self.read_register(0x0A) & 0x0F)
     }
 
     fn select_register(&self, register: u8) {
-        let nmi_disable_bit = 0;
         unsafe {
-            self.register_select
-                .write((nmi_disable_bit << 7) | register);
+            self.register_select.write(nmi::disable_bit() | register);
         }
     }
 
-    fn update_in_progress(&self) -> bool {
-        unsafe {
-            self.register_select.write(0x0A);
-            self.data.read() & 0x80 > 0
+    pub fn update_in_progress(&self) -> bool {
+        self.read_register(0x0A) & 0x80 > 0
+    }
+}
+
+static RTC_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Periodic interrupt frequency in Hz, zero while the interrupt is disabled.
+static RTC_FREQUENCY: AtomicU32 = AtomicU32::new(0);
+/// Tick count and uptime at the last change of rate.
+static RTC_EPOCH_TICKS: AtomicU64 = AtomicU64::new(0);
+static RTC_EPOCH_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of RTC periodic interrupts since [`Rtc::enable_irq`].
+pub fn rtc_ticks() -> u64 {
+    RTC_TICKS.load(Ordering::Acquire)
+}
+
+/// Time since the RTC periodic interrupt was enabled in nanoseconds, or `None` if it is not.
+pub fn rtc_uptime_ns() -> Option<u64> {
+    match RTC_FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        frequency => {
+            let ticks = rtc_ticks() - RTC_EPOCH_TICKS.load(Ordering::Acquire);
+            let ns = (ticks as u128 * 1_000_000_000 / frequency as u128) as u64;
+            Some(RTC_EPOCH_NS.load(Ordering::Acquire) + ns)
         }
     }
 }
 
+/// Rate of the RTC periodic interrupt, a power of two from 2 Hz to 8 kHz.
+///
+/// [`https://wiki.osdev.org/RTC#Changing_Interrupt_Rate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRate(u8);
+
+impl RtcRate {
+    /// 1024 Hz, the rate the RTC powers up with.
+    pub const DEFAULT: Self = Self(6);
+
+    /// Rates 1 and 2 wrap around to 256 and 128 Hz on real hardware, and 0 disables the
+    /// interrupt.
+    const fn from_value(value: u8) -> Option<Self> {
+        match value {
+            3..=15 => Some(Self(value)),
+            _ => None,
+        }
+    }
+
+    pub const fn from_frequency(frequency: u32) -> Option<Self> {
+        if frequency < 2 || frequency > 8192 || !frequency.is_power_of_two() {
+            return None;
+        }
+        Some(Self(16 - frequency.trailing_zeros() as u8))
+    }
+
+    pub const fn frequency(&self) -> u32 {
+        32768 >> (self.0 - 1)
+    }
+}
+
+lazy_static! {
+    static ref CENTURY_REGISTER: Option<u8> = acpi::century_register();
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SetTimeError {
+    InvalidDateTime,
+    InvalidAlarmTime,
+    /// The RTC cannot represent the year.
+    YearOutOfRange,
+}
+
+/// Callback of the alarm, only accessed outside of interrupt context.
+static ALARM_CALLBACK: SpinLock<Option<Box<dyn FnMut()>>> = SpinLock::new(None);
+static ALARM_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Time of day the RTC alarm fires at. A `None` field matches any value, e.g. only setting
+/// `second` fires once a minute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmTime {
+    /// 0-23, regardless of the RTC's hour format.
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
+}
+
+impl AlarmTime {
+    /// Register values from `0xC0` match any value.
+    const ANY: u8 = 0xC0;
+
+    pub const fn new(hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            hour: Some(hour),
+            minute: Some(minute),
+            second: Some(second),
+        }
+    }
+
+    /// Whether every set field is in range.
+    pub fn is_valid(&self) -> bool {
+        self.hour.is_none_or(|hour| hour < 24)
+            && self.minute.is_none_or(|minute| minute < 60)
+            && self.second.is_none_or(|second| second < 60)
+    }
+}
+
+fn run_alarm() {
+    // Taken out of the lock, so the callback may set or clear the alarm itself
+    let Some(mut callback) = ALARM_CALLBACK.lock().take() else {
+        return;
+    };
+    callback();
+
+    let mut slot = ALARM_CALLBACK.lock();
+    if slot.is_none() && ALARM_ARMED.load(Ordering::Acquire) {
+        *slot = Some(callback);
+    }
+}
+
+fn bcd_to_binary(bcd: u8) -> u8 {
+    (bcd & 0x0F) + ((bcd / 16) * 10)
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Decodes a register value other than the hour, given status register B.
+fn decode_binary(raw: u8, reg_b: u8) -> u8 {
+    if (reg_b & 0x04) == 0 {
+        bcd_to_binary(raw)
+    } else {
+        raw
+    }
+}
+
+/// Inverse of [`decode_binary`].
+fn encode_binary(value: u8, reg_b: u8) -> u8 {
+    if (reg_b & 0x04) == 0 {
+        binary_to_bcd(value)
+    } else {
+        value
+    }
+}
+
+/// Decodes a raw hour register into 0-23, given status register B.
+///
+/// In 12 hour mode bit 7 marks PM, and 12 AM is midnight.
+fn decode_hour(raw: u8, reg_b: u8) -> u8 {
+    let pm = raw & 0x80 != 0;
+    let hour = decode_binary(raw & 0x7F, reg_b);
+
+    if (reg_b & 0x02) == 0 {
+        hour % 12 + if pm { 12 } else { 0 }
+    } else {
+        hour
+    }
+}
+
+/// Inverse of [`decode_hour`].
+fn encode_hour(hour: u8, reg_b: u8) -> u8 {
+    if (reg_b & 0x02) == 0 {
+        let pm = if hour >= 12 { 0x80 } else { 0 };
+        let hour = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        encode_binary(hour, reg_b) | pm
+    } else {
+        encode_binary(hour, reg_b)
+    }
+}
+
+/// Register values as read, for comparing consecutive reads.
+#[derive(PartialEq, Eq)]
+struct RawRtc {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+/// A decoded RTC reading, with `hour` in 24 hour format.
 #[derive(Debug)]
 pub struct Rtc {
     pub second: u8,
@@ -173,46 +475,231 @@ pub struct Rtc {
     pub hour: u8,
     pub day: u8,
     pub month: u8,
+    /// Year within the century.
     pub year: u8,
+    /// Read from the CMOS register named by the FADT, if any.
+    pub century: Option<u8>,
 }
 
 impl Rtc {
+    /// The full year. Without a century register, years before 70 are taken to be in the 2000s.
+    pub fn full_year(&self) -> u16 {
+        match self.century {
+            Some(century) => century as u16 * 100 + self.year as u16,
+            None if self.year < 70 => 2000 + self.year as u16,
+            None => 1900 + self.year as u16,
+        }
+    }
+
+    pub fn datetime(&self) -> DateTime {
+        DateTime {
+            year: self.full_year(),
+            month: self.month,
+            day: self.day,
+            hour: self.hour,
+            minute: self.minute,
+            second: self.second,
+            offset: UtcOffset::UTC,
+        }
+    }
+
+    /// Starts the periodic interrupt at `rate`, counting [`rtc_ticks`], and dispatches the alarm
+    /// set with [`Cmos::set_alarm`].
+    ///
+    /// The returned [`Cmos`] is shared with the interrupt handler, and is the only one that may
+    /// be used from then on.
     pub fn enable_irq(
         port_manager: &mut PortManager,
         interrupt_lookup: &InterruptLookup,
         pic: &mut Pic,
-    ) {
-        let cmos = Cmos::new(port_manager);
-
-        unsafe {
-            // disable NMI
-            cmos.register_select.write(0x8B);
-            let prev = cmos.data.read();
-            cmos.register_select.write(0x8B);
-            cmos.data.write(prev | 0x40);
-
-            // enable NMI & flush
-            cmos.read_register(0xC);
-        }
+        rate: RtcRate,
+    ) -> Arc<Cmos> {
+        let cmos = Arc::new(Cmos::new(port_manager));
+        cmos.set_periodic_rate(rate);
+        RTC_FREQUENCY.store(rate.frequency(), Ordering::Release);
+        cmos.update_register(0x0B, |reg_b| reg_b | 0x40);
+        // Flush register C, an interrupt may already be pending
+        cmos.read_register(0x0C);
 
+        let alarm = DEFERRED_WORK.register(run_alarm);
         let pic_id = IrqId::Pic2(0);
-        pic.unmask(pic_id);
+        let handler_cmos = cmos.clone();
         interrupt_lookup.register_handler(InterruptHandler::Pic(PicHandler::new(
             pic_id,
             move || {
-                // Flush c register, allow next interrupt
+                // Reading register C acknowledges the interrupt and allows the next one
                 //
                 // https://wiki.osdev.org/RTC
-                cmos.read_register(0xC);
+                let flags = handler_cmos.read_register(0x0C);
+                if flags & 0x40 != 0 {
+                    RTC_TICKS.fetch_add(1, Ordering::AcqRel);
+                }
+                if flags & 0x20 != 0 {
+                    alarm.schedule();
+                }
             },
         )));
+        pic.unmask(pic_id);
+
+        cmos
+    }
+}
+
+/// Offset of a local time from UTC.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UtcOffset(i32);
+
+impl UtcOffset {
+    pub const UTC: Self = Self(0);
+
+    pub const fn from_seconds(seconds: i32) -> Self {
+        Self(seconds)
+    }
+
+    /// `hours` and `minutes` should have the same sign, e.g. `-3, -30` for UTC-03:30.
+    pub const fn from_hm(hours: i8, minutes: i8) -> Self {
+        Self(hours as i32 * 3600 + minutes as i32 * 60)
+    }
+
+    pub const fn as_seconds(&self) -> i32 {
+        self.0
+    }
+}
+
+impl Display for UtcOffset {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "Z");
+        }
+
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        let minutes = self.0.unsigned_abs() / 60;
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+/// A calendar date and time of day in the proleptic Gregorian calendar, at `offset` from UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub offset: UtcOffset,
+}
+
+impl DateTime {
+    const SECONDS_PER_DAY: i64 = 86_400;
+
+    /// The local time at `offset` for seconds since 1970-01-01T00:00:00Z. Years are limited to
+    /// `0..=65535`.
+    pub fn from_unix_timestamp(timestamp: i64, offset: UtcOffset) -> Self {
+        let local = timestamp + offset.as_seconds() as i64;
+        let days = local.div_euclid(Self::SECONDS_PER_DAY);
+        let seconds = local.rem_euclid(Self::SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            offset,
+        }
+    }
+
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub fn unix_timestamp(&self) -> i64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        days * Self::SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+            - self.offset.as_seconds() as i64
+    }
+
+    /// The same instant as local time at `offset`.
+    pub fn to_offset(&self, offset: UtcOffset) -> Self {
+        Self::from_unix_timestamp(self.unix_timestamp(), offset)
+    }
+
+    /// Whether every field is in range, e.g. there is no February 30th.
+    pub fn is_valid(&self) -> bool {
+        let leap_year = self.year.is_multiple_of(4)
+            && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400));
+        let days_in_month = match self.month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+
+        (1..=12).contains(&self.month)
+            && (1..=days_in_month).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
     }
+
+    /// 1 for Sunday through 7 for Saturday, as in the RTC's weekday register.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        let days = self.unix_timestamp().div_euclid(Self::SECONDS_PER_DAY);
+        ((days + 4).rem_euclid(7) + 1) as u8
+    }
+}
+
+/// Formats as ISO 8601, e.g. `2024-02-29T12:34:56Z`.
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.offset
+        )
+    }
+}
+
+/// Days since 1970-01-01 for a Gregorian date.
+///
+/// [`https://howardhinnant.github.io/date_algorithms.html#days_from_civil`]
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+///
+/// [`https://howardhinnant.github.io/date_algorithms.html#civil_from_days`]
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{debug, test_case};
+    use alloc::format;
 
     test_case!(time, {
         let mut port_manager = PortManager::default();
@@ -222,5 +709,213 @@ mod tests {
         test_assert_eq!(cmos.second(), rtc.second);
         test_assert_eq!(cmos.minute(), rtc.minute);
         test_assert_eq!(cmos.hour(), rtc.hour);
+        test_assert!(rtc.hour < 24);
+        test_assert!(cmos.datetime().year >= 2000);
+    });
+
+    test_case!(rtc_rate, {
+        test_assert_eq!(Some(RtcRate(15)), RtcRate::from_frequency(2));
+        test_assert_eq!(Some(RtcRate::DEFAULT), RtcRate::from_frequency(1024));
+        test_assert_eq!(Some(RtcRate(3)), RtcRate::from_frequency(8192));
+        test_assert_eq!(None, RtcRate::from_frequency(1));
+        test_assert_eq!(None, RtcRate::from_frequency(1000));
+        test_assert_eq!(None, RtcRate::from_frequency(16384));
+        test_assert_eq!(1024, RtcRate::DEFAULT.frequency());
+        test_assert_eq!(None, RtcRate::from_value(2));
+
+        let mut port_manager = PortManager::default();
+        let cmos = Cmos::new(&mut port_manager);
+        let previous = cmos.periodic_rate();
+        let rate = RtcRate::from_frequency(64).unwrap();
+        cmos.set_periodic_rate(rate);
+        test_assert_eq!(Some(rate), cmos.periodic_rate());
+        if let Some(previous) = previous {
+            cmos.set_periodic_rate(previous);
+        }
+    });
+
+    test_case!(rtc_alarm, {
+        let mut port_manager = PortManager::default();
+        let cmos = Cmos::new(&mut port_manager);
+
+        test_assert_eq!(None, cmos.alarm());
+        let time = AlarmTime::new(23, 59, 30);
+        test_assert_eq!(Ok(()), cmos.set_alarm(time, || {}));
+        test_assert_eq!(Some(time), cmos.alarm());
+
+        let every_minute = AlarmTime {
+            second: Some(0),
+            ..Default::default()
+        };
+        test_assert_eq!(Ok(()), cmos.set_alarm(every_minute, || {}));
+        test_assert_eq!(Some(every_minute), cmos.alarm());
+
+        // Out of range values are rejected, leaving the previous alarm in place
+        for invalid in [
+            AlarmTime {
+                hour: Some(25),
+                ..Default::default()
+            },
+            AlarmTime {
+                minute: Some(60),
+                ..Default::default()
+            },
+            AlarmTime {
+                second: Some(200),
+                ..Default::default()
+            },
+        ] {
+            test_assert_eq!(
+                Err(SetTimeError::InvalidAlarmTime),
+                cmos.set_alarm(invalid, || {})
+            );
+        }
+        test_assert_eq!(Some(every_minute), cmos.alarm());
+
+        cmos.clear_alarm();
+        test_assert_eq!(None, cmos.alarm());
+        test_assert!(ALARM_CALLBACK.lock().is_none());
+    });
+
+    test_case!(rtc_set_datetime, {
+        let mut port_manager = PortManager::default();
+        let cmos = Cmos::new(&mut port_manager);
+
+        let invalid = DateTime {
+            year: 2023,
+            month: 2,
+            day: 29,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            offset: UtcOffset::UTC,
+        };
+        test_assert_eq!(
+            Err(SetTimeError::InvalidDateTime),
+            cmos.set_datetime(&invalid)
+        );
+
+        // Writing back the current time, adjusted for the read, leaves the clock as it was
+        let now = cmos.datetime();
+        test_assert_eq!(Ok(()), cmos.set_datetime(&now));
+        let after = cmos.datetime();
+        test_assert!((0..=1).contains(&(after.unix_timestamp() - now.unix_timestamp())));
+
+        // The same instant in another time zone
+        let local = now.to_offset(UtcOffset::from_hm(5, 30));
+        test_assert_eq!(Ok(()), cmos.set_datetime(&local));
+        let after = cmos.datetime();
+        test_assert!((0..=1).contains(&(after.unix_timestamp() - now.unix_timestamp())));
+        test_assert_eq!(UtcOffset::UTC, after.offset);
+    });
+
+    test_case!(datetime_validity, {
+        let date = |year, month, day| DateTime {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            offset: UtcOffset::UTC,
+        };
+        test_assert!(date(2024, 2, 29).is_valid());
+        test_assert!(date(2000, 2, 29).is_valid());
+        test_assert!(!date(1900, 2, 29).is_valid());
+        test_assert!(!date(2024, 4, 31).is_valid());
+        test_assert!(!date(2024, 13, 1).is_valid());
+        test_assert!(!date(2024, 1, 0).is_valid());
+        test_assert!(!DateTime {
+            hour: 24,
+            ..date(2024, 1, 1)
+        }
+        .is_valid());
+
+        // Thursday and Sunday
+        test_assert_eq!(5, date(1970, 1, 1).weekday());
+        test_assert_eq!(1, date(2024, 3, 3).weekday());
+        test_assert_eq!(7, date(1969, 12, 27).weekday());
+    });
+
+    test_case!(rtc_decode_hour, {
+        // BCD, 12 hour mode
+        test_assert_eq!(0, decode_hour(0x12, 0x00));
+        test_assert_eq!(11, decode_hour(0x11, 0x00));
+        test_assert_eq!(12, decode_hour(0x92, 0x00));
+        test_assert_eq!(23, decode_hour(0x91, 0x00));
+        // Binary, 12 hour mode
+        test_assert_eq!(13, decode_hour(0x81, 0x04));
+        // BCD and binary, 24 hour mode
+        test_assert_eq!(23, decode_hour(0x23, 0x02));
+        test_assert_eq!(23, decode_hour(23, 0x06));
+
+        for reg_b in [0x00, 0x02, 0x04, 0x06] {
+            for hour in 0..24 {
+                test_assert_eq!(hour, decode_hour(encode_hour(hour, reg_b), reg_b));
+            }
+        }
+        test_assert_eq!(0x12, encode_hour(0, 0x00));
+        test_assert_eq!(0x92, encode_hour(12, 0x00));
+        test_assert_eq!(0x59, encode_binary(59, 0x02));
+
+        let rtc = Rtc {
+            second: 0,
+            minute: 0,
+            hour: 0,
+            day: 1,
+            month: 1,
+            year: 24,
+            century: None,
+        };
+        test_assert_eq!(2024, rtc.full_year());
+        test_assert_eq!(1999, Rtc { year: 99, ..rtc }.full_year());
+        test_assert_eq!(
+            2124,
+            Rtc {
+                century: Some(21),
+                ..rtc
+            }
+            .full_year()
+        );
+    });
+
+    test_case!(datetime_unix, {
+        let epoch = DateTime::from_unix_timestamp(0, UtcOffset::UTC);
+        test_assert_eq!(
+            (1970, 1, 1, 0),
+            (epoch.year, epoch.month, epoch.day, epoch.hour)
+        );
+
+        let leap_day = DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 12,
+            minute: 34,
+            second: 56,
+            offset: UtcOffset::UTC,
+        };
+        test_assert_eq!(1_709_210_096, leap_day.unix_timestamp());
+        test_assert_eq!(
+            leap_day,
+            DateTime::from_unix_timestamp(1_709_210_096, UtcOffset::UTC)
+        );
+        test_assert_eq!(
+            -1,
+            DateTime::from_unix_timestamp(-1, UtcOffset::UTC).unix_timestamp()
+        );
+        test_assert_eq!(
+            2_147_483_648,
+            DateTime::from_unix_timestamp(2_147_483_648, UtcOffset::UTC).unix_timestamp()
+        );
+
+        let local = leap_day.to_offset(UtcOffset::from_hm(-3, -30));
+        test_assert_eq!((9, 4), (local.hour, local.minute));
+        test_assert_eq!("2024-02-29T09:04:56-03:30", format!("{}", local));
+        test_assert_eq!("2024-02-29T12:34:56Z", format!("{}", leap_day));
+        test_assert_eq!(leap_day.unix_timestamp(), local.unix_timestamp());
+
+        let late = leap_day.to_offset(UtcOffset::from_hm(14, 0));
+        test_assert_eq!((3, 1, 2), (late.month, late.day, late.hour));
     });
 }
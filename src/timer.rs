@@ -0,0 +1,359 @@
+use crate::{
+    clock::Duration,
+    deferred::{TaskletHandle, DEFERRED_WORK},
+    interrupt,
+    lock::spinlock::SpinLock,
+    pit,
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use lazy_static::lazy_static;
+
+const WHEEL_SIZE: usize = 256;
+
+/// Set by [`init`], so the tick interrupt never initializes [`TIMERS`] itself.
+static TIMERS_READY: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    pub static ref TIMERS: TimerWheel =
+        TimerWheel::new(Some(DEFERRED_WORK.register(|| TIMERS.run_expired())));
+}
+
+/// Sets up the global timer wheel. Must run before the PIT interrupt is enabled for timers to
+/// fire.
+pub fn init() {
+    lazy_static::initialize(&TIMERS);
+    TIMERS_READY.store(true, Ordering::Release);
+}
+
+#[derive(Debug)]
+pub enum TimerError {
+    /// Timers count PIT ticks, so they cannot be added before the PIT is started.
+    PitNotRunning,
+}
+
+/// Runs `callback` once, `delay` from now.
+pub fn after(delay: Duration, callback: impl FnMut() + 'static) -> Result<TimerHandle, TimerError> {
+    TIMERS.after(delay, callback)
+}
+
+/// Runs `callback` every `period`, starting one period from now.
+pub fn every(
+    period: Duration,
+    callback: impl FnMut() + 'static,
+) -> Result<TimerHandle, TimerError> {
+    TIMERS.every(period, callback)
+}
+
+/// Called by the PIT interrupt with the new tick count.
+pub fn on_tick(ticks: u64) {
+    if TIMERS_READY.load(Ordering::Acquire) {
+        TIMERS.on_tick(ticks);
+    }
+}
+
+/// A consistent reading of the tick count and time a [`TimerWheel`] runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelTime {
+    pub tick: u64,
+    pub ns: u64,
+    pub tick_period_ns: u64,
+}
+
+impl WheelTime {
+    /// The PIT's time, or `None` if it is not running.
+    pub fn current() -> Option<Self> {
+        interrupt::without_interrupts(|| {
+            Some(Self {
+                tick: pit::ticks(),
+                ns: pit::uptime_ns()?,
+                tick_period_ns: pit::tick_period_ns()?,
+            })
+        })
+    }
+
+    /// The first tick at or after `deadline_ns`, but no earlier than the next tick.
+    fn tick_at(&self, deadline_ns: u64) -> u64 {
+        let remaining = deadline_ns.saturating_sub(self.ns);
+        self.tick + remaining.div_ceil(self.tick_period_ns.max(1)).max(1)
+    }
+}
+
+/// Hashed timing wheel on top of the PIT tick.
+///
+/// Timers hash into one of [`WHEEL_SIZE`] slots by the tick they expire on, so inserting is
+/// constant time and each tick only looks at one slot. Timers further out than one revolution
+/// simply stay in their slot until their tick comes around.
+///
+/// The tick interrupt only compares the tick count against the earliest expiry and schedules a
+/// tasklet, callbacks run from [`DEFERRED_WORK`] with interrupts enabled. Deadlines are kept in
+/// nanoseconds, so periodic timers whose period is not a whole number of ticks do not drift.
+///
+/// [`http://www.cs.columbia.edu/~nahum/w6998/papers/sosp87-timing-wheels.pdf`]
+pub struct TimerWheel {
+    slots: SpinLock<Vec<Vec<Arc<Timer>>>>,
+    /// The next tick whose slot has not been expired yet.
+    processed: AtomicU64,
+    /// Tick of the earliest pending timer.
+    next_expiry: AtomicU64,
+    tasklet: Option<TaskletHandle>,
+    /// Reads the current time, [`WheelTime::current`] unless the wheel is driven by hand.
+    clock: fn() -> Option<WheelTime>,
+}
+
+unsafe impl Send for TimerWheel {}
+unsafe impl Sync for TimerWheel {}
+
+impl TimerWheel {
+    /// A wheel whose expired timers are run by `tasklet`. [`TIMERS`] is the one driven by the PIT.
+    pub fn new(tasklet: Option<TaskletHandle>) -> Self {
+        Self::with_clock(tasklet, WheelTime::current)
+    }
+
+    /// Like [`Self::new`], but reading the time from `clock` instead of the PIT.
+    pub fn with_clock(tasklet: Option<TaskletHandle>, clock: fn() -> Option<WheelTime>) -> Self {
+        Self {
+            slots: SpinLock::new((0..WHEEL_SIZE).map(|_| Vec::new()).collect()),
+            processed: AtomicU64::new(0),
+            next_expiry: AtomicU64::new(u64::MAX),
+            tasklet,
+            clock,
+        }
+    }
+
+    /// Runs `callback` once, `delay` from now. Must not be called from an interrupt handler.
+    pub fn after(
+        &self,
+        delay: Duration,
+        callback: impl FnMut() + 'static,
+    ) -> Result<TimerHandle, TimerError> {
+        Ok(self.add(self.now()?, delay, None, callback))
+    }
+
+    /// Runs `callback` every `period`, starting one period from now. Must not be called from an
+    /// interrupt handler.
+    pub fn every(
+        &self,
+        period: Duration,
+        callback: impl FnMut() + 'static,
+    ) -> Result<TimerHandle, TimerError> {
+        Ok(self.add(self.now()?, period, Some(period), callback))
+    }
+
+    /// Runs the callbacks of every timer that expired by the current time.
+    pub fn run_expired(&self) {
+        if let Some(now) = (self.clock)() {
+            self.expire(now);
+        }
+    }
+
+    fn now(&self) -> Result<WheelTime, TimerError> {
+        (self.clock)().ok_or(TimerError::PitNotRunning)
+    }
+
+    fn add(
+        &self,
+        now: WheelTime,
+        delay: Duration,
+        period: Option<Duration>,
+        callback: impl FnMut() + 'static,
+    ) -> TimerHandle {
+        let as_ns = |duration: Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+
+        let timer = Arc::new(Timer {
+            active: AtomicBool::new(true),
+            expires: AtomicU64::new(0),
+            deadline_ns: AtomicU64::new(now.ns.saturating_add(as_ns(delay))),
+            period_ns: period.map(as_ns).unwrap_or(0),
+            callback: SpinLock::new(Box::new(callback)),
+        });
+        self.insert(now, timer.clone());
+
+        TimerHandle(timer)
+    }
+
+    fn insert(&self, now: WheelTime, timer: Arc<Timer>) {
+        let expires = now.tick_at(timer.deadline_ns.load(Ordering::Acquire));
+        timer.expires.store(expires, Ordering::Release);
+
+        self.slots.lock()[expires as usize % WHEEL_SIZE].push(timer);
+        self.next_expiry.fetch_min(expires, Ordering::AcqRel);
+    }
+
+    fn on_tick(&self, ticks: u64) {
+        if ticks >= self.next_expiry.load(Ordering::Acquire) {
+            // Recomputed by `expire`
+            self.next_expiry.store(u64::MAX, Ordering::Release);
+            if let Some(tasklet) = &self.tasklet {
+                tasklet.schedule();
+            }
+        }
+    }
+
+    /// Runs the callbacks of every timer that expired by `now`, and re-arms periodic ones.
+    fn expire(&self, now: WheelTime) {
+        let mut expired = Vec::new();
+        {
+            let mut slots = self.slots.lock();
+
+            // After a full revolution every slot has been visited
+            let first = self.processed.load(Ordering::Acquire);
+            let count = (now.tick + 1).saturating_sub(first).min(WHEEL_SIZE as u64);
+            for tick in first..first + count {
+                slots[tick as usize % WHEEL_SIZE].retain(|timer| {
+                    if !timer.is_active() {
+                        false
+                    } else if timer.expires.load(Ordering::Acquire) <= now.tick {
+                        expired.push(timer.clone());
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+            self.processed.fetch_max(now.tick + 1, Ordering::AcqRel);
+
+            let next_expiry = slots
+                .iter()
+                .flatten()
+                .filter(|timer| timer.is_active())
+                .map(|timer| timer.expires.load(Ordering::Acquire))
+                .min()
+                .unwrap_or(u64::MAX);
+            self.next_expiry.store(next_expiry, Ordering::Release);
+        }
+
+        // The lock is released so that callbacks may add and cancel timers
+        for timer in expired {
+            if !timer.is_active() {
+                continue;
+            }
+            if timer.period_ns == 0 {
+                timer.active.store(false, Ordering::Release);
+            }
+
+            (timer.callback.lock())();
+
+            if timer.period_ns != 0 && timer.is_active() {
+                // Periods that were missed entirely are skipped instead of fired back to back
+                let mut deadline = timer.deadline_ns.load(Ordering::Acquire);
+                loop {
+                    deadline = deadline.saturating_add(timer.period_ns);
+                    if deadline > now.ns {
+                        break;
+                    }
+                }
+                timer.deadline_ns.store(deadline, Ordering::Release);
+                self.insert(now, timer);
+            }
+        }
+    }
+}
+
+struct Timer {
+    active: AtomicBool,
+    /// Tick the timer's slot is expired on.
+    expires: AtomicU64,
+    deadline_ns: AtomicU64,
+    /// Zero for one-shot timers.
+    period_ns: u64,
+    callback: SpinLock<Box<dyn FnMut()>>,
+}
+
+// The kernel runs on a single core, and `callback` is only called from `TimerWheel::expire`, never
+// from an interrupt handler.
+unsafe impl Send for Timer {}
+unsafe impl Sync for Timer {}
+
+impl Timer {
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+}
+
+/// Dropping the handle does not cancel the timer; use [`Self::cancel`].
+#[derive(Clone)]
+pub struct TimerHandle(Arc<Timer>);
+
+impl TimerHandle {
+    /// Stops the timer from firing again. Safe to call from the timer's own callback and from
+    /// interrupt handlers.
+    pub fn cancel(&self) {
+        self.0.active.store(false, Ordering::Release);
+    }
+
+    /// Whether the timer will still fire, i.e. it was not cancelled and is periodic or has not
+    /// fired yet.
+    pub fn is_active(&self) -> bool {
+        self.0.is_active()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+    use core::sync::atomic::AtomicUsize;
+
+    test_case!(timer_wheel, {
+        static ONE_SHOT: AtomicUsize = AtomicUsize::new(0);
+        static PERIODIC: AtomicUsize = AtomicUsize::new(0);
+        static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+
+        // A tick of 999_847 ns, as the PIT runs at its default frequency
+        let tick_period_ns = 999_847;
+        let at = |tick: u64| WheelTime {
+            tick,
+            ns: tick * tick_period_ns,
+            tick_period_ns,
+        };
+
+        let wheel = TimerWheel::new(None);
+        let one_shot = wheel.add(at(0), Duration::from_millis(50), None, || {
+            ONE_SHOT.fetch_add(1, Ordering::Relaxed);
+        });
+        let periodic = wheel.add(
+            at(0),
+            Duration::from_millis(28),
+            Some(Duration::from_millis(28)),
+            || {
+                PERIODIC.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+        let cancelled = wheel.add(at(0), Duration::from_millis(10), None, || {
+            CANCELLED.fetch_add(1, Ordering::Relaxed);
+        });
+        cancelled.cancel();
+        test_assert!(!cancelled.is_active());
+
+        // 50 ms are 50.008 ticks, so the timer must not fire on tick 50
+        test_assert_eq!(11, wheel.next_expiry.load(Ordering::Relaxed));
+        wheel.expire(at(50));
+        test_assert_eq!(0, ONE_SHOT.load(Ordering::Relaxed));
+        test_assert_eq!(1, PERIODIC.load(Ordering::Relaxed));
+        wheel.expire(at(51));
+        test_assert_eq!(1, ONE_SHOT.load(Ordering::Relaxed));
+        test_assert!(!one_shot.is_active());
+
+        // 28 periods of 28 ms are 784.12 ticks. Stepping tick by tick, without drift, the timer
+        // fires 28 times by tick 785 and not before.
+        for tick in 52..785 {
+            wheel.on_tick(tick);
+            wheel.expire(at(tick));
+        }
+        test_assert_eq!(27, PERIODIC.load(Ordering::Relaxed));
+        wheel.expire(at(785));
+        test_assert_eq!(28, PERIODIC.load(Ordering::Relaxed));
+
+        // Missed periods fire once, far beyond a revolution of the wheel
+        wheel.expire(at(10_000));
+        test_assert_eq!(29, PERIODIC.load(Ordering::Relaxed));
+
+        periodic.cancel();
+        wheel.expire(at(20_000));
+        test_assert_eq!(29, PERIODIC.load(Ordering::Relaxed));
+        test_assert_eq!(1, ONE_SHOT.load(Ordering::Relaxed));
+        test_assert_eq!(0, CANCELLED.load(Ordering::Relaxed));
+        test_assert_eq!(u64::MAX, wheel.next_expiry.load(Ordering::Relaxed));
+    });
+}
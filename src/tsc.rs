@@ -0,0 +1,148 @@
+use crate::{
+    cpuuid::{self, CpuidFeatureEdx},
+    interrupt,
+    pit::Pit,
+    time::Cmos,
+};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Calibrated frequency in Hz, zero until calibrated.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the time stamp counter.
+///
+/// [`https://www.felixcloutier.com/x86/rdtsc`]
+pub fn read() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+pub fn is_supported() -> bool {
+    cpuuid::has_feature(CpuidFeatureEdx::TSC)
+}
+
+/// An invariant TSC runs at a constant rate in every power state, which makes it usable as a
+/// clock source.
+pub fn is_invariant() -> bool {
+    let max_extended_leaf = cpuuid::cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && cpuuid::cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Calibrated frequency in Hz.
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+pub fn set_frequency(frequency: u64) {
+    FREQUENCY.store(frequency, Ordering::Release);
+}
+
+pub fn cycles_to_ns(cycles: u64) -> Option<u64> {
+    let frequency = frequency()?;
+    Some((cycles as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
+
+pub fn ns_to_cycles(ns: u64) -> Option<u64> {
+    let frequency = frequency()?;
+    Some((ns as u128 * frequency as u128 / 1_000_000_000) as u64)
+}
+
+/// Time since the TSC was reset in nanoseconds, once calibrated.
+pub fn uptime_ns() -> Option<u64> {
+    cycles_to_ns(read())
+}
+
+/// Measures the TSC frequency against `periods` full periods of PIT channel 0.
+///
+/// Polls the channel 0 count instead of waiting for interrupts, so it works before interrupts are
+/// enabled. `pit` should run at a rate of at least a few hundred Hz, otherwise this takes long.
+pub fn calibrate_with_pit(pit: &Pit, periods: u32) -> Option<u64> {
+    if !is_supported() || periods == 0 {
+        return None;
+    }
+
+    let cycles = interrupt::without_interrupts(|| {
+        // The count decrements towards 0 and reloads, a larger count than before marks the start
+        // of a new period.
+        let wait_for_reload = || {
+            let mut previous = pit.read_count();
+            loop {
+                let count = pit.read_count();
+                if count > previous {
+                    break;
+                }
+                previous = count;
+            }
+        };
+
+        wait_for_reload();
+        let start = read();
+        for _ in 0..periods {
+            wait_for_reload();
+        }
+        read() - start
+    });
+
+    let pit_cycles = periods as u64 * pit.divisor() as u64;
+    let frequency = cycles * Pit::BASE_FREQUENCY as u64 / pit_cycles;
+    set_frequency(frequency);
+    Some(frequency)
+}
+
+/// Measures the TSC frequency over one second of the RTC. Slow, taking up to two seconds, but
+/// does not depend on the PIT.
+pub fn calibrate_with_rtc(cmos: &Cmos) -> Option<u64> {
+    if !is_supported() {
+        return None;
+    }
+
+    // The update in progress flag rises once a second, right before the clock ticks
+    let wait_for_update = || {
+        while cmos.update_in_progress() {}
+        while !cmos.update_in_progress() {}
+    };
+
+    wait_for_update();
+    let start = read();
+    wait_for_update();
+    let frequency = read() - start;
+
+    set_frequency(frequency);
+    Some(frequency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test, test_case};
+
+    test_case!(tsc_calibration, {
+        test_assert!(is_supported());
+
+        let frequency = calibrate_with_pit(test::pit(), 20);
+        test_assert!(frequency.is_some());
+        let frequency = frequency.unwrap();
+        // Anything from 10 MHz to 100 GHz is plausible
+        test_assert!((10_000_000..100_000_000_000).contains(&frequency));
+        test_assert_eq!(Some(1_000_000_000), cycles_to_ns(frequency));
+        test_assert_eq!(Some(frequency), ns_to_cycles(1_000_000_000));
+
+        let start = read();
+        test_assert!(read() > start);
+    });
+}
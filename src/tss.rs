@@ -0,0 +1,202 @@
+use core::{arch::global_asm, cell::UnsafeCell, fmt::Debug};
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 4;
+
+/// Task state of the kernel. A task switch, e.g. into the double fault task, saves the state of
+/// the interrupted code here.
+pub static TSS: TssCell = TssCell::new();
+
+/// Entered through a task gate on vector 8, so that a double fault always runs on
+/// [`DOUBLE_FAULT_STACK`], no matter the state of the faulting stack.
+pub static DOUBLE_FAULT_TSS: TssCell = TssCell::new();
+
+static DOUBLE_FAULT_STACK: DoubleFaultStack =
+    DoubleFaultStack(UnsafeCell::new([0; DOUBLE_FAULT_STACK_SIZE]));
+
+#[repr(align(16))]
+struct DoubleFaultStack(UnsafeCell<[u8; DOUBLE_FAULT_STACK_SIZE]>);
+
+unsafe impl Sync for DoubleFaultStack {}
+
+pub struct TssCell(UnsafeCell<TaskStateSegment>);
+
+unsafe impl Sync for TssCell {}
+
+impl TssCell {
+    const fn new() -> Self {
+        Self(UnsafeCell::new(TaskStateSegment::null()))
+    }
+
+    pub fn addr(&self) -> u32 {
+        self.0.get() as u32
+    }
+
+    /// Copies out the task state, which the CPU writes on every task switch.
+    pub fn get(&self) -> TaskStateSegment {
+        unsafe { core::ptr::read_volatile(self.0.get()) }
+    }
+}
+
+/// https://wiki.osdev.org/Task_State_Segment
+///
+/// Segment selectors occupy the lower 16 bits of their field, the upper half is reserved.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TaskStateSegment {
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldtr: u32,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    pub const SIZE: usize = size_of::<Self>();
+
+    pub const fn null() -> Self {
+        Self {
+            link: 0,
+            esp0: 0,
+            ss0: 0,
+            esp1: 0,
+            ss1: 0,
+            esp2: 0,
+            ss2: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            fs: 0,
+            gs: 0,
+            ldtr: 0,
+            trap: 0,
+            // No IO permission bitmap
+            iomap_base: Self::SIZE as u16,
+        }
+    }
+}
+
+impl Debug for TaskStateSegment {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TaskStateSegment")
+            .field("eip", &format_args!("{:#x}", self.eip))
+            .field("esp", &format_args!("{:#x}", self.esp))
+            .field("ebp", &format_args!("{:#x}", self.ebp))
+            .field("eflags", &format_args!("{:#x}", self.eflags))
+            .field("eax", &format_args!("{:#x}", self.eax))
+            .field("ebx", &format_args!("{:#x}", self.ebx))
+            .field("ecx", &format_args!("{:#x}", self.ecx))
+            .field("edx", &format_args!("{:#x}", self.edx))
+            .field("esi", &format_args!("{:#x}", self.esi))
+            .field("edi", &format_args!("{:#x}", self.edi))
+            .field("cs", &format_args!("{:#x}", self.cs))
+            .field("ss", &format_args!("{:#x}", self.ss))
+            .field("ds", &format_args!("{:#x}", self.ds))
+            .field("cr3", &format_args!("{:#x}", self.cr3))
+            .finish()
+    }
+}
+
+/// Fills in both task state segments. Must run before their descriptors are loaded into the GDT.
+pub fn init(code_selector: u16, data_selector: u16) {
+    let cr3: u32;
+    unsafe {
+        core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+
+    let stack_top = DOUBLE_FAULT_STACK.0.get() as u32 + DOUBLE_FAULT_STACK_SIZE as u32;
+
+    unsafe {
+        let tss = &mut *TSS.0.get();
+        tss.ss0 = data_selector as u32;
+
+        let double_fault_tss = &mut *DOUBLE_FAULT_TSS.0.get();
+        *double_fault_tss = TaskStateSegment {
+            esp0: stack_top,
+            ss0: data_selector as u32,
+            cr3,
+            eip: double_fault_task_entry as *const () as usize as u32,
+            // Interrupts disabled
+            eflags: 0x2,
+            esp: stack_top,
+            es: data_selector as u32,
+            cs: code_selector as u32,
+            ss: data_selector as u32,
+            ds: data_selector as u32,
+            fs: data_selector as u32,
+            gs: data_selector as u32,
+            ..TaskStateSegment::null()
+        };
+    }
+}
+
+extern "C" {
+    fn double_fault_task_entry();
+}
+
+// The CPU pushes the error code onto the new stack and starts executing without a return
+// address, the call lines the stack up with the C calling convention.
+global_asm!(
+    ".global double_fault_task_entry",
+    "double_fault_task_entry:",
+    "    call {report}",
+    "2:",
+    "    cli",
+    "    hlt",
+    "    jmp 2b",
+    report = sym double_fault_task,
+);
+
+extern "C" fn double_fault_task(error_code: u32) -> ! {
+    // The task switch saved the faulting state into the kernel task
+    let state = TSS.get();
+    panic!(
+        "double fault (error code {:#x}) at {:#x}\n{:#?}",
+        error_code, state.eip, state
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case;
+
+    test_case!(tss_layout, {
+        test_assert_eq!(104, TaskStateSegment::SIZE);
+        test_assert_eq!(104, TaskStateSegment::null().iomap_base);
+        test_assert_eq!(0, DOUBLE_FAULT_STACK.0.get() as usize % 16);
+    });
+}